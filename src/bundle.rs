@@ -0,0 +1,332 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::db::{Error, RecordMeta, Result};
+use crate::rle;
+use crate::soup::Soup;
+use crate::store::{self, PatternStore, Record};
+use crate::symmetry::Symmetry;
+
+/// Archives exchanged as a single file: a JSON document, or a zip of RLE
+/// files as written by the directory store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Zip,
+}
+
+impl Format {
+    /// From the extension of the file, JSON unless it is .zip
+    pub fn of(path: &Path) -> Format {
+        match path.extension() {
+            Some(e) if e.eq_ignore_ascii_case("zip") => Format::Zip,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// What to do with an imported record whose name is taken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Conflict {
+    Skip,
+    Rename,
+    Overwrite,
+}
+
+/// Counts of the imported records
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub added: usize,
+    pub renamed: usize,
+    pub overwritten: usize,
+    pub skipped: usize,
+    /// Files that could not be read, with the reason
+    pub failed: Vec<String>,
+}
+
+const JSON_FORMAT: &str = "life-cursive-bundle";
+const JSON_VERSION: i64 = 1;
+
+fn _zip_error(e: zip::result::ZipError) -> Error {
+    match e {
+        zip::result::ZipError::Io(e) => Error::Io(e),
+        e => Error::Corrupted(e.to_string()),
+    }
+}
+
+fn _to_json(name: &str, cells: &[(i32, i32)], soup: Option<&Soup>, meta: &RecordMeta) -> Value {
+    let set: HashSet<(i32, i32)> = cells.iter().cloned().collect();
+    let mut v = json!({
+        "name": name,
+        "x": meta.bbox.0,
+        "y": meta.bbox.1,
+        "rle": rle::write_rle(&set, &meta.rule),
+        "description": meta.description,
+        "author": meta.author,
+        "rule": meta.rule,
+        "generation": meta.generation,
+        "tags": meta.tags,
+        "created": meta.created,
+        "modified": meta.modified,
+    });
+    if let Some(sp) = soup {
+        v["soup"] = json!({
+            "x": sp.x,
+            "y": sp.y,
+            "width": sp.width,
+            "height": sp.height,
+            "density": sp.density,
+            "symmetry": sp.symmetry.name(),
+            "seed": sp.seed,
+        });
+    }
+    v
+}
+
+fn _from_json(v: &Value) -> Result<(String, Record)> {
+    let bad = |what: &str| Error::Corrupted(format!("record without {}", what));
+    let text = |key: &str| v[key].as_str().unwrap_or_default().to_string();
+    let name = v["name"].as_str().ok_or_else(|| bad("a name"))?.to_string();
+    let (x0, y0) = (
+        v["x"].as_i64().unwrap_or(0) as i32,
+        v["y"].as_i64().unwrap_or(0) as i32,
+    );
+    let cells: Vec<(i32, i32)> = rle::parse(v["rle"].as_str().ok_or_else(|| bad("cells"))?)
+        .map_err(Error::Corrupted)?
+        .into_iter()
+        .map(|(x, y)| (x + x0, y + y0))
+        .collect();
+    let meta = RecordMeta {
+        description: text("description"),
+        author: text("author"),
+        rule: text("rule"),
+        generation: v["generation"].as_i64().unwrap_or(0),
+        tags: v["tags"]
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|t| t.as_str())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        created: v["created"].as_i64().unwrap_or(0),
+        modified: v["modified"].as_i64().unwrap_or(0),
+        ..Default::default()
+    };
+    let s = &v["soup"];
+    let soup = if s.is_object() {
+        Some(Soup {
+            x: s["x"].as_i64().unwrap_or(0) as i32,
+            y: s["y"].as_i64().unwrap_or(0) as i32,
+            width: s["width"].as_i64().unwrap_or(0) as i32,
+            height: s["height"].as_i64().unwrap_or(0) as i32,
+            density: s["density"].as_f64().unwrap_or(0.0),
+            symmetry: Symmetry::parse(s["symmetry"].as_str().unwrap_or_default())
+                .unwrap_or(Symmetry::C1),
+            seed: s["seed"].as_u64().unwrap_or(0),
+        })
+        .filter(|sp| sp.check().is_ok())
+    } else {
+        None
+    };
+    Ok((name, (cells, soup, meta)))
+}
+
+/// Writes all the records of the store into the bundle, returns their number
+pub fn export(store: &dyn PatternStore, path: &Path) -> Result<usize> {
+    let names = store.list()?;
+    match Format::of(path) {
+        Format::Json => {
+            let mut records: Vec<Value> = vec![];
+            for name in names.iter() {
+                let cells = store.load(name)?;
+                let soup = store.load_soup(name)?;
+                records.push(_to_json(
+                    name,
+                    &cells,
+                    soup.as_ref(),
+                    &store.load_meta(name)?,
+                ));
+            }
+            let doc = json!({"format": JSON_FORMAT, "version": JSON_VERSION, "records": records});
+            std::fs::write(path, serde_json::to_string_pretty(&doc).unwrap())?;
+        }
+        Format::Zip => {
+            let mut zip = zip::ZipWriter::new(File::create(path)?);
+            let options = zip::write::FileOptions::default();
+            for name in names.iter() {
+                let cells: HashSet<(i32, i32)> = store.load(name)?.into_iter().collect();
+                let soup = store.load_soup(name)?;
+                let text =
+                    store::write_rle_file(name, &cells, soup.as_ref(), &store.load_meta(name)?);
+                zip.start_file(store::file_name(name), options)
+                    .map_err(_zip_error)?;
+                zip.write_all(text.as_bytes())?;
+            }
+            zip.finish().map_err(_zip_error)?;
+        }
+    }
+    Ok(names.len())
+}
+
+/// Records of the bundle, with their names
+pub fn read(path: &Path) -> Result<Vec<(String, Record)>> {
+    let mut res: Vec<(String, Record)> = vec![];
+    match Format::of(path) {
+        Format::Json => {
+            let doc: Value = serde_json::from_str(&std::fs::read_to_string(path)?)
+                .map_err(|e| Error::Corrupted(e.to_string()))?;
+            if doc["format"] != JSON_FORMAT {
+                return Err(Error::Corrupted(format!(
+                    "{} is not a pattern bundle",
+                    path.display()
+                )));
+            }
+            for v in doc["records"].as_array().into_iter().flatten() {
+                res.push(_from_json(v)?);
+            }
+        }
+        Format::Zip => {
+            let mut zip = zip::ZipArchive::new(File::open(path)?).map_err(_zip_error)?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i).map_err(_zip_error)?;
+                let stem = match file.name().strip_suffix(".rle") {
+                    Some(stem) if file.is_file() => stem.rsplit('/').next().unwrap().to_string(),
+                    _ => continue,
+                };
+                let name =
+                    store::record_name(&stem).ok_or_else(|| Error::Corrupted(stem.clone()))?;
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                res.push((name, store::read_rle_file(&text)?));
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Name not yet in the store: "name (2)", "name (3)"...
+fn _free_name(store: &dyn PatternStore, name: &str) -> Result<String> {
+    let mut n = 2;
+    loop {
+        let candidate = format!("{} ({})", name, n);
+        if !store.exists(&candidate)? {
+            return Ok(candidate);
+        }
+        n += 1;
+    }
+}
+
+/// Adds one record to the store, resolving a name conflict as asked
+pub fn add(
+    store: &mut dyn PatternStore,
+    name: &str,
+    record: Record,
+    conflict: Conflict,
+    report: &mut ImportReport,
+) -> Result<()> {
+    let (cells, soup, meta) = record;
+    let cells: HashSet<(i32, i32)> = cells.into_iter().collect();
+    if !store.exists(name)? {
+        store.import_record(name, &cells, soup.as_ref(), &meta, false)?;
+        report.added += 1;
+        return Ok(());
+    }
+    match conflict {
+        Conflict::Skip => report.skipped += 1,
+        Conflict::Rename => {
+            let new_name = _free_name(store, name)?;
+            store.import_record(&new_name, &cells, soup.as_ref(), &meta, false)?;
+            report.renamed += 1;
+        }
+        Conflict::Overwrite => {
+            store.import_record(name, &cells, soup.as_ref(), &meta, true)?;
+            report.overwritten += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Adds the records of the bundle to the store, all of them or, if one
+/// fails, none.
+pub fn import(
+    store: &mut dyn PatternStore,
+    path: &Path,
+    conflict: Conflict,
+) -> Result<ImportReport> {
+    let mut records = read(path)?;
+    let mut report = ImportReport::default();
+    store.atomically(&mut |store| {
+        for (name, record) in std::mem::take(&mut records) {
+            add(store, &name, record, conflict, &mut report)?;
+        }
+        Ok(())
+    })?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patterns;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_export_import() {
+        let mut src = MemoryStore::new();
+        let glider: HashSet<(i32, i32)> = patterns::get("Glider").unwrap().into_iter().collect();
+        let soup = Soup {
+            x: 0,
+            y: 0,
+            width: 3,
+            height: 3,
+            density: 0.5,
+            symmetry: Symmetry::C2,
+            seed: 7,
+        };
+        let meta = RecordMeta {
+            description: String::from("ship"),
+            tags: vec![String::from("c/4")],
+            ..Default::default()
+        };
+        let dated = RecordMeta {
+            created: 1000,
+            modified: 2000,
+            ..meta.clone()
+        };
+        src.import_record("glider", &glider, Some(&soup), &dated, false)
+            .unwrap();
+        src.save("a/b", &HashSet::new(), None, &RecordMeta::default())
+            .unwrap();
+        for ext in ["json", "zip"] {
+            let path =
+                std::env::temp_dir().join(format!("lf-bundle-{}.{}", std::process::id(), ext));
+            assert_eq!(export(&src, &path).unwrap(), 2);
+            let mut dst = MemoryStore::new();
+            dst.save("glider", &HashSet::new(), None, &RecordMeta::default())
+                .unwrap();
+            let report = import(&mut dst, &path, Conflict::Skip).unwrap();
+            assert_eq!((report.added, report.skipped), (1, 1));
+            assert_eq!(dst.load("glider").unwrap(), vec![]);
+            let report = import(&mut dst, &path, Conflict::Rename).unwrap();
+            assert_eq!((report.added, report.renamed), (0, 2));
+            assert_eq!(
+                dst.list().unwrap(),
+                vec!["a/b", "a/b (2)", "glider", "glider (2)"]
+            );
+            let report = import(&mut dst, &path, Conflict::Overwrite).unwrap();
+            assert_eq!(report.overwritten, 2);
+            let m = dst.load_meta("glider").unwrap();
+            assert_eq!((m.population, m.description.as_str()), (5, "ship"));
+            assert_eq!(m.tags, meta.tags);
+            assert_eq!((m.created, m.modified), (1000, 2000));
+            assert_eq!(dst.load_soup("glider").unwrap(), Some(soup.clone()));
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
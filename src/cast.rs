@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use serde_json::json;
+
+/// Writer of asciinema v2 recordings: a JSON header line, then one
+/// `[time, "o", data]` line per chunk of terminal output.
+pub struct CastWriter<W: Write> {
+    out: W,
+    start: Instant,
+}
+
+impl CastWriter<BufWriter<File>> {
+    pub fn create(path: &Path, width: usize, height: usize) -> io::Result<Self> {
+        CastWriter::new(BufWriter::new(File::create(path)?), width, height)
+    }
+}
+
+impl<W: Write> CastWriter<W> {
+    pub fn new(mut out: W, width: usize, height: usize) -> io::Result<Self> {
+        let header = json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": crate::db::now(),
+            "env": {"TERM": "xterm-256color"},
+        });
+        writeln!(out, "{}", header)?;
+        Ok(CastWriter {
+            out,
+            start: Instant::now(),
+        })
+    }
+
+    fn _event(&mut self, code: &str, data: &str) -> io::Result<()> {
+        let t = self.start.elapsed().as_secs_f64();
+        writeln!(self.out, "{}", json!([(t * 1e6).round() / 1e6, code, data]))
+    }
+
+    /// Terminal output, escape sequences included
+    pub fn output(&mut self, data: &str) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self._event("o", data)
+    }
+
+    /// New size of the terminal
+    pub fn resize(&mut self, width: usize, height: usize) -> io::Result<()> {
+        self._event("r", &format!("{}x{}", width, height))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Writes the field alone, one text frame per generation: a
+/// `#G <generation> <population> <x> <y>` line giving the top left corner
+/// of the region, then its rows of '.' and 'O'.
+pub struct FrameRecorder<W: Write> {
+    out: W,
+    pub frames: usize,
+}
+
+impl FrameRecorder<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(FrameRecorder::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> FrameRecorder<W> {
+    pub fn new(out: W) -> Self {
+        FrameRecorder { out, frames: 0 }
+    }
+
+    pub fn frame(
+        &mut self,
+        generation: i64,
+        cells: &HashSet<(i32, i32)>,
+        region: (i32, i32, i32, i32),
+    ) -> io::Result<()> {
+        let (x0, y0, w, h) = region;
+        writeln!(self.out, "#G {} {} {} {}", generation, cells.len(), x0, y0)?;
+        for y in y0..y0 + h {
+            let row: String = (x0..x0 + w)
+                .map(|x| if cells.contains(&(x, y)) { 'O' } else { '.' })
+                .collect();
+            writeln!(self.out, "{}", row)?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorders() {
+        let mut cast = CastWriter::new(vec![], 80, 24).unwrap();
+        cast.output("\x1b[1;1Hhi\"").unwrap();
+        cast.output("").unwrap();
+        cast.resize(100, 30).unwrap();
+        let text = String::from_utf8(cast.out).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            (lines[0]["version"].as_i64(), lines[0]["width"].as_i64()),
+            (Some(2), Some(80))
+        );
+        assert_eq!(
+            (&lines[1][1], &lines[1][2]),
+            (&json!("o"), &json!("\x1b[1;1Hhi\""))
+        );
+        assert_eq!(lines[2][2], "100x30");
+        let mut frames = FrameRecorder::new(vec![]);
+        let blinker: HashSet<(i32, i32)> = [(0, 0), (1, 0), (2, 0)].into_iter().collect();
+        frames.frame(3, &blinker, (-1, 0, 4, 2)).unwrap();
+        assert_eq!(
+            String::from_utf8(frames.out).unwrap(),
+            "#G 3 3 -1 0\n.OOO\n....\n"
+        );
+    }
+}
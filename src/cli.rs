@@ -0,0 +1,365 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use life_cursive::bundle::{self, Conflict};
+use life_cursive::db::{self, RecordMeta};
+use life_cursive::formats::{self, Format};
+use life_cursive::macrocell::Tree;
+use life_cursive::rule::{self, Rule};
+use life_cursive::store::{self, Record};
+use life_cursive::universe::Universe;
+
+/// Subcommands run without the terminal UI
+pub const COMMANDS: [&str; 4] = ["run", "stats", "import", "export"];
+
+enum Failure {
+    /// Bad command line, exit code 2
+    Usage(String),
+    Failed(String),
+}
+
+impl From<db::Error> for Failure {
+    fn from(e: db::Error) -> Failure {
+        Failure::Failed(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Failure {
+        Failure::Failed(e.to_string())
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    rule: Option<Rule>,
+    gens: Option<i64>,
+    output: Option<PathBuf>,
+    db: Option<PathBuf>,
+    conflict: Option<Conflict>,
+    name: Option<String>,
+    paths: Vec<PathBuf>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, Failure> {
+        let mut opts = Options::default();
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            if !arg.starts_with('-') || arg == "-" {
+                opts.paths.push(PathBuf::from(arg));
+                continue;
+            }
+            let (key, inline) = match arg.split_once('=') {
+                Some((k, v)) => (k, Some(v.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| it.next().cloned())
+                    .ok_or_else(|| Failure::Usage(format!("{} needs a value", key)))
+            };
+            match key {
+                "--rule" => opts.rule = Some(Rule::parse(&value()?).map_err(Failure::Usage)?),
+                "--gens" => {
+                    let v = value()?;
+                    opts.gens = Some(
+                        v.parse()
+                            .ok()
+                            .filter(|&n: &i64| n >= 0)
+                            .ok_or_else(|| Failure::Usage(format!("Bad --gens '{}'", v)))?,
+                    )
+                }
+                "-o" | "--output" => opts.output = Some(PathBuf::from(value()?)),
+                "--db" => opts.db = Some(PathBuf::from(value()?)),
+                "--name" => opts.name = Some(value()?),
+                "--conflict" => {
+                    opts.conflict = Some(match value()?.as_str() {
+                        "skip" => Conflict::Skip,
+                        "rename" => Conflict::Rename,
+                        "overwrite" => Conflict::Overwrite,
+                        v => return Err(Failure::Usage(format!("Bad --conflict '{}'", v))),
+                    })
+                }
+                _ => return Err(Failure::Usage(format!("Unknown argument '{}'", arg))),
+            }
+        }
+        Ok(opts)
+    }
+
+    fn one_path(&self) -> Result<&Path, Failure> {
+        match self.paths.as_slice() {
+            [p] => Ok(p),
+            [] => Err(Failure::Usage(String::from("Missing file"))),
+            _ => Err(Failure::Usage(String::from("Too many files"))),
+        }
+    }
+
+    /// The archive, resolved as for the terminal UI
+    fn archive(&self) -> Result<Box<dyn store::PatternStore>, Failure> {
+        let args: Vec<String> = match &self.db {
+            Some(p) => vec![String::from("--db"), p.to_string_lossy().into_owned()],
+            None => vec![],
+        };
+        let path = config::db_path(&args).map_err(Failure::Usage)?;
+        config::prepare_dir(&path)?;
+        Ok(store::open(&path)?)
+    }
+}
+
+/// Text of the file, or of the standard input for "-"
+fn _read_text(path: &Path) -> Result<String, Failure> {
+    let mut text = String::new();
+    if path == Path::new("-") {
+        std::io::stdin().read_to_string(&mut text)?;
+    } else {
+        text = std::fs::read_to_string(path)?;
+    }
+    Ok(text)
+}
+
+/// Pattern of the text, any format
+fn _read_input(text: &str) -> Result<Record, Failure> {
+    Ok(formats::parse(text, formats::detect(text))?)
+}
+
+/// Macrocell pattern of the text, kept as a quadtree however large it is
+fn _read_tree(text: &str) -> Result<Option<Tree>, Failure> {
+    if formats::detect(text) != Format::Macrocell {
+        return Ok(None);
+    }
+    Ok(Some(Tree::parse(text).map_err(Failure::Failed)?))
+}
+
+fn _tree_meta(tree: &Tree) -> RecordMeta {
+    RecordMeta {
+        description: tree.description.clone(),
+        rule: tree.rule.clone(),
+        generation: tree.generation,
+        ..Default::default()
+    }
+}
+
+/// Rule from the option, else from the pattern, else Life
+fn _rule(opts: &Options, meta: &RecordMeta) -> Result<Rule, Failure> {
+    match opts.rule {
+        Some(r) => Ok(r),
+        None if meta.rule.is_empty() => Ok(rule::LIFE),
+        None => Rule::parse(&meta.rule).map_err(Failure::Failed),
+    }
+}
+
+fn _run(opts: &Options) -> Result<(), Failure> {
+    let input = opts.one_path()?;
+    let text = _read_text(input)?;
+    let output = opts.output.as_deref().filter(|out| *out != Path::new("-"));
+    let format = output.and_then(Format::of).unwrap_or(Format::Rle);
+    // Macrocell patterns run as a quadtree, and stay one if written as such
+    let mut tree = _read_tree(&text)?;
+    let (cells, meta) = match tree.as_mut() {
+        Some(tree) => {
+            let rule = _rule(opts, &_tree_meta(tree))?;
+            tree.advance(&rule, opts.gens.unwrap_or(1) as u64);
+            tree.rule = rule.to_string();
+            let cells = match format {
+                Format::Macrocell => HashSet::new(),
+                _ => tree.cells().map_err(Failure::Failed)?,
+            };
+            (cells, _tree_meta(tree))
+        }
+        None => {
+            let (cells, _, meta) = _read_input(&text)?;
+            let mut u = Universe::with_cells(_rule(opts, &meta)?, cells);
+            u.generation = meta.generation;
+            u.run(opts.gens.unwrap_or(1));
+            let meta = RecordMeta {
+                rule: u.rule.to_string(),
+                generation: u.generation,
+                ..meta
+            };
+            (u.cells, meta)
+        }
+    };
+    let name = opts.name.clone().unwrap_or_else(|| {
+        input
+            .file_stem()
+            .filter(|_| input != Path::new("-"))
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("pattern"))
+    });
+    let text = match tree {
+        Some(tree) if format == Format::Macrocell => tree.write(),
+        _ => formats::write_pattern(format, &name, &cells, &meta)?,
+    };
+    match output {
+        Some(out) => std::fs::write(out, text)?,
+        None => std::io::stdout().write_all(text.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// Population of every generation as CSV
+fn _stats(opts: &Options) -> Result<(), Failure> {
+    let text = _read_text(opts.one_path()?)?;
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    writeln!(out, "generation,population")?;
+    if let Some(mut tree) = _read_tree(&text)? {
+        let rule = _rule(opts, &_tree_meta(&tree))?;
+        for gen in 0..=opts.gens.unwrap_or(100) {
+            if gen > 0 {
+                tree.advance(&rule, 1);
+            }
+            writeln!(out, "{},{}", tree.generation, tree.population())?;
+        }
+        return Ok(());
+    }
+    let (cells, _, meta) = _read_input(&text)?;
+    let mut u = Universe::with_cells(_rule(opts, &meta)?, cells);
+    u.generation = meta.generation;
+    for gen in 0..=opts.gens.unwrap_or(100) {
+        if gen > 0 {
+            u.step();
+        }
+        writeln!(out, "{},{}", u.generation, u.population())?;
+    }
+    Ok(())
+}
+
+/// Bundles, pattern files or directories of them into the archive
+fn _import(opts: &Options) -> Result<(), Failure> {
+    if opts.paths.is_empty() {
+        return Err(Failure::Usage(String::from("Missing file")));
+    }
+    let mut archive = opts.archive()?;
+    let conflict = opts.conflict.unwrap_or(Conflict::Skip);
+    let mut failed = 0;
+    for path in opts.paths.iter() {
+        let bundled = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json") || e.eq_ignore_ascii_case("zip"));
+        let r = if bundled {
+            bundle::import(archive.as_mut(), path, conflict)?
+        } else {
+            formats::import_path(archive.as_mut(), path, conflict)?
+        };
+        println!(
+            "{}: added {}, renamed {}, overwritten {}, skipped {}",
+            path.display(),
+            r.added,
+            r.renamed,
+            r.overwritten,
+            r.skipped
+        );
+        for f in r.failed.iter() {
+            eprintln!("{}", f);
+        }
+        failed += r.failed.len();
+    }
+    match failed {
+        0 => Ok(()),
+        n => Err(Failure::Failed(format!("{} files could not be read", n))),
+    }
+}
+
+/// The archive as a bundle, or one record of it as a pattern file
+fn _export(opts: &Options) -> Result<(), Failure> {
+    let out = opts.one_path()?;
+    let archive = opts.archive()?;
+    match &opts.name {
+        Some(name) => {
+            let cells: HashSet<(i32, i32)> = archive.load(name)?.into_iter().collect();
+            let meta = archive.load_meta(name)?;
+            let format = Format::of(out).unwrap_or(Format::Rle);
+            std::fs::write(out, formats::write_pattern(format, name, &cells, &meta)?)?;
+        }
+        None => {
+            let n = bundle::export(archive.as_ref(), out)?;
+            println!("Exported {} records to {}", n, out.display());
+        }
+    }
+    Ok(())
+}
+
+/// Runs the subcommand `args[0]`, returns the exit code
+pub fn main(args: &[String]) -> i32 {
+    let res = Options::parse(&args[1..]).and_then(|opts| match args[0].as_str() {
+        "run" => _run(&opts),
+        "stats" => _stats(&opts),
+        "import" => _import(&opts),
+        "export" => _export(&opts),
+        cmd => Err(Failure::Usage(format!("Unknown command '{}'", cmd))),
+    });
+    match res {
+        Ok(()) => 0,
+        Err(Failure::Usage(e)) => {
+            eprintln!("{}\n\n{}", e, config::USAGE);
+            2
+        }
+        Err(Failure::Failed(e)) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use life_cursive::{patterns, universe};
+
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("lf-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let glider: HashSet<(i32, i32)> = patterns::get("Glider").unwrap().into_iter().collect();
+        let input = dir.join("glider.cells");
+        let meta = RecordMeta::default();
+        std::fs::write(
+            &input,
+            formats::write_pattern(Format::Cells, "glider", &glider, &meta).unwrap(),
+        )
+        .unwrap();
+        let args: Vec<String> = ["run", "--gens=4", "--rule", "23/3"]
+            .iter()
+            .map(|s| s.to_string())
+            .chain([
+                input.to_string_lossy().into_owned(),
+                String::from("-o"),
+                dir.join("out.lif").to_string_lossy().into_owned(),
+            ])
+            .collect();
+        assert_eq!(main(&args), 0);
+        let (cells, _, _) = formats::read_file(&dir.join("out.lif")).unwrap();
+        let (x0, y0, _, _) = universe::bbox(&glider);
+        let moved: HashSet<(i32, i32)> = glider
+            .iter()
+            .map(|(x, y)| (x - x0 + 1, y - y0 + 1))
+            .collect();
+        assert_eq!(cells.into_iter().collect::<HashSet<(i32, i32)>>(), moved);
+        // Macrocell files are run as a quadtree, in place
+        let input = dir.join("glider.mc");
+        std::fs::write(
+            &input,
+            formats::write_pattern(Format::Macrocell, "glider", &glider, &meta).unwrap(),
+        )
+        .unwrap();
+        let out = dir.join("out.mc");
+        let args: Vec<String> = ["run", "--gens=40", input.to_str().unwrap(), "-o"]
+            .iter()
+            .map(|s| s.to_string())
+            .chain([out.to_string_lossy().into_owned()])
+            .collect();
+        assert_eq!(main(&args), 0);
+        let mut u = Universe::with_cells(rule::LIFE, glider.iter().cloned());
+        u.run(40);
+        let (cells, _, meta) = formats::read_file(&out).unwrap();
+        assert_eq!(cells.into_iter().collect::<HashSet<(i32, i32)>>(), u.cells);
+        assert_eq!((meta.generation, meta.rule.as_str()), (40, "B3/S23"));
+        assert_eq!(main(&[String::from("run")]), 2);
+        assert_eq!(main(&[String::from("stats"), String::from("--gens")]), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
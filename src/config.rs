@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Environment variable naming the archive
+pub const DB_ENV: &str = "LIFE_CURSIVE_DB";
+const APP_DIR: &str = "life-cursive";
+const DB_FILE: &str = "lf.db";
+
+pub const USAGE: &str = "Usage: life-cursive [--db <archive>]
+       life-cursive run [--rule <rule>] [--gens <n>] <input> [-o <output>]
+       life-cursive stats [--rule <rule>] [--gens <n>] <input>
+       life-cursive import [--db <archive>] [--conflict skip|rename|overwrite] <file>...
+       life-cursive export [--db <archive>] [--name <record>] <file>
+
+Without a command the terminal UI is started. 'run' writes the pattern after
+n generations (1 by default) to the output, in the format of its extension,
+or as RLE to the standard output. 'stats' prints the population of every
+generation up to n (100 by default) as CSV. Macrocell (.mc) inputs are run
+as a quadtree, however large. The input '-' is the standard input. 'import' adds bundles (.json, .zip), pattern files and directories of
+them to the archive; 'export' writes the archive as a bundle, or a single
+record as a pattern file.
+
+The archive is taken from --db, the LIFE_CURSIVE_DB environment variable,
+the 'db = <path>' line of <config dir>/life-cursive/config, or else is
+<data dir>/life-cursive/lf.db. An existing directory is used as an archive
+of RLE files, one per record.";
+
+/// Path of the configuration file, e.g. ~/.config/life-cursive/config
+pub fn config_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join(APP_DIR).join("config"))
+}
+
+/// Default archive in the data directory, e.g. ~/.local/share/life-cursive/lf.db
+pub fn default_db() -> PathBuf {
+    dirs::data_dir()
+        .map(|d| d.join(APP_DIR))
+        .unwrap_or_default()
+        .join(DB_FILE)
+}
+
+/// Value of the `db` key of the configuration. Lines are `key = value`,
+/// '#' starts a comment.
+pub fn parse_config(text: &str) -> Option<PathBuf> {
+    text.lines()
+        .map(|l| l.split('#').next().unwrap().trim())
+        .filter_map(|l| l.split_once('='))
+        .find(|(k, _)| k.trim() == "db")
+        .map(|(_, v)| PathBuf::from(v.trim()))
+        .filter(|p| !p.as_os_str().is_empty())
+}
+
+/// The --db option of the command line
+pub fn parse_args(args: &[String]) -> Result<Option<PathBuf>, String> {
+    let mut db: Option<PathBuf> = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if arg == "--db" {
+            match it.next() {
+                Some(p) => db = Some(PathBuf::from(p)),
+                None => return Err(String::from("--db needs a path")),
+            }
+        } else if let Some(p) = arg.strip_prefix("--db=") {
+            db = Some(PathBuf::from(p));
+        } else {
+            return Err(format!("Unknown argument '{}'", arg));
+        }
+    }
+    Ok(db)
+}
+
+/// Archive to open: command line first, then environment, configuration
+/// file and the default.
+pub fn db_path(args: &[String]) -> Result<PathBuf, String> {
+    if let Some(p) = parse_args(args)? {
+        return Ok(p);
+    }
+    if let Some(p) = std::env::var_os(DB_ENV).filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(p));
+    }
+    let configured = config_file()
+        .and_then(|f| fs::read_to_string(f).ok())
+        .and_then(|text| parse_config(&text));
+    Ok(configured.unwrap_or_else(default_db))
+}
+
+/// Creates the directory of the archive if needed
+pub fn prepare_dir(db: &Path) -> std::io::Result<()> {
+    match db.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_and_args() {
+        let text = "# archive\nrule = B3/S23\n db = /tmp/life.db # mine\n";
+        assert_eq!(parse_config(text), Some(PathBuf::from("/tmp/life.db")));
+        assert_eq!(parse_config("db =\n"), None);
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(
+            parse_args(&args(&["--db", "a.db"])),
+            Ok(Some(PathBuf::from("a.db")))
+        );
+        assert_eq!(
+            parse_args(&args(&["--db=b.db"])),
+            Ok(Some(PathBuf::from("b.db")))
+        );
+        assert_eq!(parse_args(&args(&[])), Ok(None));
+        assert!(parse_args(&args(&["--db"])).is_err());
+        assert!(parse_args(&args(&["-x"])).is_err());
+    }
+}
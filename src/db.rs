@@ -1,116 +1,932 @@
 extern crate rusqlite;
-use rusqlite::Connection;
-pub use rusqlite::{Result, Error};
+use rusqlite::{Connection, ErrorCode};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::patterns;
+use crate::rle;
+use crate::store::{self, PatternStore};
+use crate::soup::Soup;
+use crate::symmetry::Symmetry;
+use crate::universe::bbox;
 
 pub struct Storage {
     conn: Connection,
 }
 
+/// Errors of the storage
+#[derive(Debug)]
+pub enum Error {
+    /// No record of that name
+    NotFound(String),
+    /// A record of that name already exists
+    DuplicateName(String),
+    /// The archive or a record in it cannot be read
+    Corrupted(String),
+    /// The archive was written by a newer version of the program, with the
+    /// given schema version
+    TooNew(usize),
+    /// The kind of archive cannot do that
+    Unsupported(&'static str),
+    Io(std::io::Error),
+    Sql(rusqlite::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotFound(name) => write!(f, "There is no record named '{}'", name),
+            Error::DuplicateName(name) => write!(f, "A record named '{}' already exists", name),
+            Error::Corrupted(what) => write!(f, "Corrupted archive: {}", what),
+            Error::Unsupported(what) => write!(f, "This kind of archive does not support {}", what),
+            Error::TooNew(version) => write!(f, "The archive has schema version {}, newer than this program knows", version),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Sql(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn _error_code(e: &rusqlite::Error) -> Option<ErrorCode> {
+    match e {
+        rusqlite::Error::SqliteFailure(err, _) => Some(err.code),
+        _ => None,
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Error {
+        match _error_code(&e) {
+            Some(ErrorCode::NotADatabase) | Some(ErrorCode::DatabaseCorrupt) => Error::Corrupted(e.to_string()),
+            _ => Error::Sql(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Turns the violation of the unique name into Error::DuplicateName
+fn _unique_name<T>(res: rusqlite::Result<T>, name: &str) -> Result<T> {
+    match res {
+        Err(e) if _error_code(&e) == Some(ErrorCode::ConstraintViolation) => Err(Error::DuplicateName(name.to_string())),
+        res => Ok(res?),
+    }
+}
+
+enum Migration {
+    Sql(&'static str),
+    /// For the changes that cannot be made in SQL alone
+    Code(fn(&Connection) -> Result<()>),
+}
+
+/// Schema migrations in order; `PRAGMA user_version` holds the number of the
+/// ones applied. Databases created before the versioning have version 0 but
+/// already contain some of the tables, hence the "if not exists" in the
+/// first three.
+const MIGRATIONS: &[Migration] = &[
+    // 1: records and their cells
+    Migration::Sql("create table if not exists records (
+        id integer primary key,
+        name text not null unique
+    );
+    create table if not exists lcells (
+        id integer primary key,
+        record_id integer not null references records(id),
+        x integer not null,
+        y integer not null
+    );"),
+    // 2: soup parameters
+    Migration::Sql("create table if not exists soups (
+        record_id integer primary key references records(id),
+        seed integer not null,
+        density real not null,
+        symmetry text not null,
+        x integer not null,
+        y integer not null,
+        width integer not null,
+        height integer not null
+    );"),
+    // 3: metadata
+    Migration::Sql("create table if not exists record_meta (
+        record_id integer primary key references records(id),
+        description text not null,
+        author text not null,
+        rule text not null,
+        generation integer not null,
+        population integer not null,
+        bbox_x integer not null,
+        bbox_y integer not null,
+        bbox_w integer not null,
+        bbox_h integer not null,
+        created integer not null,
+        modified integer not null,
+        tags text not null
+    );"),
+    // 4: previous revisions of the records
+    Migration::Sql("create table revisions (
+        id integer primary key,
+        record_id integer not null references records(id),
+        number integer not null,
+        created integer not null,
+        generation integer not null,
+        population integer not null,
+        unique (record_id, number)
+    );
+    create table revision_cells (
+        id integer primary key,
+        revision_id integer not null references revisions(id),
+        x integer not null,
+        y integer not null
+    );"),
+    // 5: cells as RLE in the records and revisions instead of a row per cell
+    Migration::Code(_cells_to_rle),
+    // 6: hashes of the patterns for finding duplicates
+    Migration::Code(_add_hashes),
+    // 7: soup and metadata of the revisions
+    Migration::Sql("create table revision_soups (
+        revision_id integer primary key references revisions(id),
+        seed integer not null,
+        density real not null,
+        symmetry text not null,
+        x integer not null,
+        y integer not null,
+        width integer not null,
+        height integer not null
+    );
+    create table revision_meta (
+        revision_id integer primary key references revisions(id),
+        description text not null,
+        author text not null,
+        rule text not null,
+        created integer not null,
+        tags text not null
+    );"),
+];
+
+fn _add_hashes(conn: &Connection) -> Result<()> {
+    conn.execute_batch("alter table records add column hash text not null default '';
+        alter table records add column hash_oriented text not null default '';
+        create index records_hash on records(hash);
+        create index records_hash_oriented on records(hash_oriented);")?;
+    let mut recs: Vec<(i64, i32, i32, String)> = vec![];
+    {
+        let mut sel = conn.prepare("SELECT id, cells_x, cells_y, cells from records;")?;
+        let rows = sel.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?;
+        for row in rows {
+            recs.push(row?);
+        }
+    }
+    for (id, x0, y0, text) in recs {
+        let cells = _decode_cells(x0, y0, &text)?;
+        conn.execute("update records set hash=?1, hash_oriented=?2 where id=?3", rusqlite::params![
+            patterns::pattern_hash(&cells, false), patterns::pattern_hash(&cells, true), id
+        ])?;
+    }
+    Ok(())
+}
+
+fn _cells_to_rle(conn: &Connection) -> Result<()> {
+    conn.execute_batch("alter table records add column cells_x integer not null default 0;
+        alter table records add column cells_y integer not null default 0;
+        alter table records add column cells text not null default '!';
+        alter table revisions add column cells_x integer not null default 0;
+        alter table revisions add column cells_y integer not null default 0;
+        alter table revisions add column cells text not null default '!';")?;
+    for (table, cells_table, id_column) in [("records", "lcells", "record_id"), ("revisions", "revision_cells", "revision_id")] {
+        let mut grouped: HashMap<i64, HashSet<(i32, i32)>> = HashMap::new();
+        {
+            let mut sel = conn.prepare(&format!("SELECT {}, x, y from {};", id_column, cells_table))?;
+            let rows = sel.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            for row in rows {
+                let (id, x, y): (i64, i32, i32) = row?;
+                grouped.entry(id).or_default().insert((x, y));
+            }
+        }
+        for (id, cells) in grouped {
+            let (x0, y0, text) = _encode_cells(&cells);
+            conn.execute(&format!("update {} set cells_x=?1, cells_y=?2, cells=?3 where id=?4", table),
+                rusqlite::params![x0, y0, text, id])?;
+        }
+        conn.execute_batch(&format!("drop table {};", cells_table))?;
+    }
+    Ok(())
+}
+
+/// Cells as stored: the origin of the bounding box and the RLE relative to it
+fn _encode_cells(cells: &HashSet<(i32, i32)>) -> (i32, i32, String) {
+    let (x0, y0, _, _) = bbox(cells);
+    (x0, y0, rle::write_rle(cells, ""))
+}
+
+fn _decode_cells(x0: i32, y0: i32, text: &str) -> Result<Vec<(i32, i32)>> {
+    let cells = rle::parse(text).map_err(Error::Corrupted)?;
+    cells
+        .into_iter()
+        .map(|(x, y)| match (x.checked_add(x0), y.checked_add(y0)) {
+            (Some(x), Some(y)) => Ok((x, y)),
+            _ => Err(Error::Corrupted(String::from("Cells out of range"))),
+        })
+        .collect()
+}
+
+/// Descriptive data saved along with the cells of a record.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecordMeta {
+    pub description: String,
+    pub author: String,
+    pub rule: String,
+    pub generation: i64,
+    pub population: i64,
+    /// Bounding box as (x, y, width, height)
+    pub bbox: (i32, i32, i32, i32),
+    /// Unix timestamps, seconds
+    pub created: i64,
+    pub modified: i64,
+    pub tags: Vec<String>,
+}
+
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(_) => 0,
+    }
+}
+
+/// A saved state of a record. The current one is the last revision.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Revision {
+    pub number: i64,
+    /// Unix timestamp of the save
+    pub created: i64,
+    pub generation: i64,
+    pub population: i64,
+}
+
+/// Id of the record, Error::NotFound if there is no such record
+fn _record_id(conn: &Connection, name: &str) -> Result<i64> {
+    match conn.query_row("SELECT id from records WHERE name=?1", [name], |row| row.get(0)) {
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(Error::NotFound(name.to_string())),
+        res => Ok(res?),
+    }
+}
+
+/// Cells, soup and metadata of the record with the given id
+fn _write_record(conn: &Connection, rid: i64, cells: &HashSet<(i32, i32)>, soup: Option<&Soup>, meta: &RecordMeta, created: i64, modified: i64) -> Result<()> {
+    let (x0, y0, text) = _encode_cells(cells);
+    let list: Vec<(i32, i32)> = cells.iter().cloned().collect();
+    conn.execute("update records set cells_x=?1, cells_y=?2, cells=?3, hash=?4, hash_oriented=?5 where id=?6",
+        rusqlite::params![x0, y0, text, patterns::pattern_hash(&list, false), patterns::pattern_hash(&list, true), rid])?;
+    if let Some(sp) = soup {
+        conn.execute("insert into soups (record_id, seed, density, symmetry, x, y, width, height)
+            values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)", rusqlite::params![
+            rid, sp.seed as i64, sp.density, sp.symmetry.name(), sp.x, sp.y, sp.width, sp.height
+        ])?;
+    }
+    let (bx, by, bw, bh) = bbox(cells);
+    conn.execute("insert into record_meta (record_id, description, author, rule, generation, population,
+        bbox_x, bbox_y, bbox_w, bbox_h, created, modified, tags)
+        values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)", rusqlite::params![
+        rid, meta.description, meta.author, meta.rule, meta.generation, cells.len() as i64,
+        bx, by, bw, bh, created, modified, meta.tags.join(",")
+    ])?;
+    Ok(())
+}
+
+/// Formats a unix timestamp as "YYYY-MM-DD HH:MM" (UTC).
+pub fn format_timestamp(ts: i64) -> String {
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = ts.div_euclid(86400) + 719468;
+    let secs = ts.rem_euclid(86400);
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", y, m, d, secs / 3600, secs % 3600 / 60)
+}
+
 impl Storage {
-    pub fn new(fname: &str) -> Storage {
-        let storage = Storage {
-            conn: Connection::open(fname).unwrap(),
+    /// Opens or creates the archive and brings its schema up to date.
+    pub fn new(fname: &str) -> Result<Storage> {
+        let mut storage = Storage {
+            conn: Connection::open(fname)?,
         };
-        storage.conn.execute("create table if not exists records (
-            id integer primary key,
-            name text not null unique
-        )", []).unwrap();
-        match storage.conn.execute("create table if not exists lcells (
-            id integer primary key,
-            record_id integer not null references records(id),
-            x integer not null,
-            y integer not null
-        )", []) {
-            Ok(_) => {},
-            Err(s) => {
-                println!("{}", s);
-                panic!("{}", s);
-            },
-        }
-        storage
-    }
-
-    pub fn delete(&mut self, name: &str) -> Result<()> {
-        let recs = self.get_records()?;
-        let mut rid: i64 = -1;
-        for (id, n) in recs {
-              if n == String::from(name) {
-                rid = id;
-                break;
-            }
+        storage.migrate(fname)?;
+        Ok(storage)
+    }
+
+    pub fn schema_version(&self) -> Result<usize> {
+        let v: i64 = self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        Ok(v as usize)
+    }
+
+    /// Brings the schema up to date. An existing database is first copied
+    /// next to the original, then all the pending migrations run in a
+    /// single transaction.
+    fn migrate(&mut self, fname: &str) -> Result<()> {
+        let version = self.schema_version()?;
+        if version > MIGRATIONS.len() {
+            return Err(Error::TooNew(version));
+        }
+        if version == MIGRATIONS.len() {
+            return Ok(());
         }
-        if rid < 0i64 {
-            return Err(Error::InvalidQuery);
+        let tables: i64 = self.conn.query_row("SELECT count(*) from sqlite_master", [], |row| row.get(0))?;
+        if tables > 0 && fname != ":memory:" {
+            let mut backup = format!("{}.v{}.bak", fname, version);
+            if std::path::Path::new(&backup).exists() {
+                backup = format!("{}.v{}.{}.bak", fname, version, now());
+            }
+            self.conn.execute("VACUUM INTO ?1", [&backup])?;
         }
         let tx = self.conn.transaction()?;
-        tx.execute("delete from lcells where record_id=(?1)", &[&rid])?;
-        tx.execute("delete from records where id=(?1)", &[&rid])?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            match migration {
+                Migration::Sql(sql) => tx.execute_batch(sql)?,
+                Migration::Code(f) => f(&tx)?,
+            }
+            tx.pragma_update(None, "user_version", (i + 1) as i64)?;
+        }
+        Ok(tx.commit()?)
+    }
+
+    /// Saves a new record with the given timestamps
+    fn insert(&mut self, name: &str, cells: &HashSet<(i32, i32)>, soup: Option<&Soup>, meta: &RecordMeta, created: i64, modified: i64) -> Result<()> {
+        let tx = self.conn.savepoint()?;
+        _unique_name(tx.execute("insert into records (name) values (?1)", [&name.to_string()]), name)?;
+        let last_id = tx.last_insert_rowid();
+        _write_record(&tx, last_id, cells, soup, meta, created, modified)?;
         tx.commit()?;
         Ok(())
     }
 
-    pub fn save(&mut self, name: &str, cells: &HashSet<(i32, i32)>) -> Result<i64> {
-        let tx = self.conn.transaction()?;
-        tx.execute("insert into records (name) values (?1)", &[&name.to_string()])?;
-        let last_id = tx.last_insert_rowid();
+    /// Replaces the contents of the record with the given timestamps
+    fn replace(&mut self, name: &str, cells: &HashSet<(i32, i32)>, soup: Option<&Soup>, meta: &RecordMeta, created: i64, modified: i64) -> Result<()> {
+        let old = self.load_meta(name)?;
+        let tx = self.conn.savepoint()?;
+        let rid = _record_id(&tx, name)?;
+        // The current state becomes the last of the previous revisions
+        let number: i64 = tx.query_row("SELECT count(*) + 1 from revisions WHERE record_id=?1", [&rid], |row| row.get(0))?;
+        tx.execute("insert into revisions (record_id, number, created, generation, population, cells_x, cells_y, cells)
+            select id, ?2, ?3, ?4, ?5, cells_x, cells_y, cells from records where id=?1",
+            [&rid, &number, &old.modified, &old.generation, &old.population])?;
+        let rev_id = tx.last_insert_rowid();
+        tx.execute("insert into revision_soups (revision_id, seed, density, symmetry, x, y, width, height)
+            select ?1, seed, density, symmetry, x, y, width, height from soups where record_id=?2", [&rev_id, &rid])?;
+        tx.execute("insert into revision_meta (revision_id, description, author, rule, created, tags)
+            select ?1, description, author, rule, created, tags from record_meta where record_id=?2", [&rev_id, &rid])?;
+        tx.execute("delete from soups where record_id=(?1)", [&rid])?;
+        tx.execute("delete from record_meta where record_id=(?1)", [&rid])?;
+        _write_record(&tx, rid, cells, soup, meta, created, modified)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl PatternStore for Storage {
+    fn delete(&mut self, name: &str) -> Result<()> {
+        let rid = _record_id(&self.conn, name)?;
+        let tx = self.conn.savepoint()?;
+        tx.execute("delete from soups where record_id=(?1)", [&rid])?;
+        tx.execute("delete from record_meta where record_id=(?1)", [&rid])?;
+        tx.execute("delete from revision_soups where revision_id in (select id from revisions where record_id=?1)", [&rid])?;
+        tx.execute("delete from revision_meta where revision_id in (select id from revisions where record_id=?1)", [&rid])?;
+        tx.execute("delete from revisions where record_id=(?1)", [&rid])?;
+        tx.execute("delete from records where id=(?1)", [&rid])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn save(&mut self, name: &str, cells: &HashSet<(i32, i32)>, soup: Option<&Soup>, meta: &RecordMeta) -> Result<()> {
+        let ts = now();
+        self.insert(name, cells, soup, meta, ts, ts)
+    }
+
+    fn overwrite(&mut self, name: &str, cells: &HashSet<(i32, i32)>, soup: Option<&Soup>, meta: &RecordMeta) -> Result<()> {
+        let old = self.load_meta(name)?;
+        let created = if old.created > 0 { old.created } else { now() };
+        self.replace(name, cells, soup, meta, created, now())
+    }
 
-        for (x, y) in cells.iter() {
-            let (xx, yy) = (*x as i64, *y as i64);
-            tx.execute("insert into lcells (record_id, x, y) values (?1, ?2, ?3)", &[&last_id, &xx, &yy])?;
+    fn import_record(&mut self, name: &str, cells: &HashSet<(i32, i32)>, soup: Option<&Soup>, meta: &RecordMeta, replace: bool) -> Result<()> {
+        let (created, modified) = store::kept_times(meta);
+        if replace {
+            self.replace(name, cells, soup, meta, created, modified)
+        } else {
+            self.insert(name, cells, soup, meta, created, modified)
         }
+    }
+
+    fn atomically(&mut self, f: &mut dyn FnMut(&mut dyn PatternStore) -> Result<()>) -> Result<()> {
+        // The methods use savepoints of their own, which nest in this one
+        self.conn.execute_batch("SAVEPOINT atomically")?;
+        let res = f(self);
+        match res {
+            Ok(_) => self.conn.execute_batch("RELEASE atomically")?,
+            Err(_) => self.conn.execute_batch("ROLLBACK TO atomically; RELEASE atomically")?,
+        }
+        res
+    }
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        match _record_id(&self.conn, name) {
+            Ok(_) => Ok(true),
+            Err(Error::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        let tx = self.conn.savepoint()?;
+        let rid = _record_id(&tx, name)?;
+        _unique_name(tx.execute("update records set name=?1 where id=?2", rusqlite::params![new_name, rid]), new_name)?;
+        tx.execute("update record_meta set modified=?1 where record_id=?2", rusqlite::params![now(), rid])?;
+        Ok(tx.commit()?)
+    }
+
+    fn duplicate(&mut self, name: &str, new_name: &str) -> Result<()> {
+        let tx = self.conn.savepoint()?;
+        let rid = _record_id(&tx, name)?;
+        _unique_name(tx.execute("insert into records (name, cells_x, cells_y, cells, hash, hash_oriented)
+            select ?1, cells_x, cells_y, cells, hash, hash_oriented from records where id=?2", rusqlite::params![new_name, rid]), new_name)?;
+        let new_id = tx.last_insert_rowid();
+        tx.execute("insert into soups (record_id, seed, density, symmetry, x, y, width, height)
+            select ?1, seed, density, symmetry, x, y, width, height from soups where record_id=?2", [&new_id, &rid])?;
+        tx.execute("insert into record_meta (record_id, description, author, rule, generation, population,
+            bbox_x, bbox_y, bbox_w, bbox_h, created, modified, tags)
+            select ?1, description, author, rule, generation, population,
+            bbox_x, bbox_y, bbox_w, bbox_h, ?3, ?3, tags from record_meta where record_id=?2", [&new_id, &rid, &now()])?;
         tx.commit()?;
-        Ok(last_id)
+        Ok(())
     }
 
-    pub fn get_records(&self) -> Result<Vec<(i64, String)>> {
-        let mut recs: Vec<(i64, String)> = vec![];
-        let mut sel = self.conn.prepare("SELECT id, name from records;")?;
-        let rows = sel.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?))
-        })?;
-        for idn in rows {
-            let ir = idn?;
-            recs.push((ir.0, ir.1));
-        } 
-        Ok(recs)
+    fn list(&self) -> Result<Vec<String>> {
+        let mut sel = self.conn.prepare("SELECT name from records order by name;")?;
+        let rows = sel.query_map([], |row| row.get(0))?;
+        let mut res: Vec<String> = vec![];
+        for name in rows {
+            res.push(name?);
+        }
+        Ok(res)
     }
 
-    pub fn load(&self, name: &str) -> Result<Vec<(i32, i32)>> {
-        let recs = self.get_records()?;
+    fn load(&self, name: &str) -> Result<Vec<(i32, i32)>> {
+        let (x0, y0, text): (i32, i32, String) = match self.conn.query_row(
+            "SELECT cells_x, cells_y, cells from records WHERE name=?1", [name],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))) {
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(Error::NotFound(name.to_string())),
+            res => res?,
+        };
+        _decode_cells(x0, y0, &text)
+    }
 
-        let mut rid: i64 = -1;
-        for (id, n) in recs {
-              if n == String::from(name) {
-                rid = id;
-                break;
+    fn find_equivalent(&self, cells: &HashSet<(i32, i32)>, oriented: bool) -> Result<Vec<String>> {
+        let list: Vec<(i32, i32)> = cells.iter().cloned().collect();
+        let hash = patterns::pattern_hash(&list, oriented);
+        if hash.is_empty() {
+            return Ok(vec![]);
+        }
+        let column = if oriented { "hash_oriented" } else { "hash" };
+        let mut sel = self.conn.prepare(&format!("SELECT name from records WHERE {}=?1 order by name;", column))?;
+        let rows = sel.query_map([&hash], |row| row.get(0))?;
+        let mut candidates: Vec<String> = vec![];
+        for name in rows {
+            candidates.push(name?);
+        }
+        // The hash only narrows down the records, they may still differ
+        let canonical = patterns::canonical(&list, oriented);
+        let mut res: Vec<String> = vec![];
+        for name in candidates {
+            if patterns::canonical(&self.load(&name)?, oriented) == canonical {
+                res.push(name);
             }
         }
-        if rid < 0i64 {
-            return Err(Error::InvalidQuery);
+        Ok(res)
+    }
+
+    fn duplicates(&self, oriented: bool) -> Result<Vec<Vec<String>>> {
+        let column = if oriented { "hash_oriented" } else { "hash" };
+        let mut sel = self.conn.prepare(&format!("SELECT {0}, name from records WHERE {0} in
+            (select {0} from records where {0} != '' group by {0} having count(*) > 1) order by {0}, name;", column))?;
+        let rows = sel.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut by_hash: Vec<Vec<String>> = vec![];
+        let mut last = String::new();
+        for row in rows {
+            let (hash, name): (String, String) = row?;
+            if hash != last || by_hash.is_empty() {
+                by_hash.push(vec![]);
+                last = hash;
+            }
+            by_hash.last_mut().unwrap().push(name);
         }
-        let mut sel = self.conn.prepare("SELECT x, y from lcells WHERE record_id=?1;")?;
-        let rows = sel.query_map(&[&rid], |row| {
-            Ok((row.get(0), row.get(1)))
-        })?;
+        // Records with the same hash are told apart by their cells
+        let mut res: Vec<Vec<String>> = vec![];
+        for names in by_hash {
+            let mut records: Vec<(String, Vec<(i32, i32)>)> = vec![];
+            for name in names {
+                let cells = self.load(&name)?;
+                records.push((name, cells));
+            }
+            res.extend(patterns::group_equivalent(records, oriented));
+        }
+        Ok(res)
+    }
+
+    fn has_revisions(&self) -> bool {
+        true
+    }
 
-        let mut res: Vec<(i32, i32)> = vec![];
-        for idn in rows {
-            let ir = idn?;
-            res.push((ir.0?, ir.1?))
+    fn revisions(&self, name: &str) -> Result<Vec<Revision>> {
+        let rid = _record_id(&self.conn, name)?;
+        let mut sel = self.conn.prepare("SELECT number, created, generation, population from revisions
+            WHERE record_id=?1 order by number;")?;
+        let rows = sel.query_map([&rid], |row| {
+            Ok(Revision {
+                number: row.get(0)?,
+                created: row.get(1)?,
+                generation: row.get(2)?,
+                population: row.get(3)?,
+            })
+        })?;
+        let mut res: Vec<Revision> = vec![];
+        for rev in rows {
+            res.push(rev?);
         }
+        let meta = self.load_meta(name)?;
+        res.push(Revision {
+            number: res.len() as i64 + 1,
+            created: meta.modified,
+            generation: meta.generation,
+            population: meta.population,
+        });
         Ok(res)
     }
+
+    fn load_revision(&self, name: &str, number: i64) -> Result<Vec<(i32, i32)>> {
+        let rid = _record_id(&self.conn, name)?;
+        let rev_id: i64 = match self.conn.query_row("SELECT id from revisions WHERE record_id=?1 and number=?2",
+            [&rid, &number], |row| row.get(0)) {
+            Ok(id) => id,
+            // Not archived: the current revision
+            Err(rusqlite::Error::QueryReturnedNoRows) => return self.load(name),
+            Err(e) => return Err(e.into()),
+        };
+        let (x0, y0, text): (i32, i32, String) = self.conn.query_row(
+            "SELECT cells_x, cells_y, cells from revisions WHERE id=?1", [&rev_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        _decode_cells(x0, y0, &text)
+    }
+
+    /// Revisions archived before they kept their soup and metadata get the
+    /// ones of the current record.
+    fn load_revision_meta(&self, name: &str, number: i64) -> Result<(Option<Soup>, RecordMeta)> {
+        let rid = _record_id(&self.conn, name)?;
+        let (rev_id, created, generation, population): (i64, i64, i64, i64) = match self.conn.query_row(
+            "SELECT id, created, generation, population from revisions WHERE record_id=?1 and number=?2",
+            [&rid, &number], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))) {
+            Ok(rev) => rev,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Ok((self.load_soup(name)?, self.load_meta(name)?)),
+            Err(e) => return Err(e.into()),
+        };
+        let current = self.load_meta(name)?;
+        let mut meta = RecordMeta { generation, population, modified: created, ..current.clone() };
+        let mut soup = self.load_soup(name)?;
+        let mut sel = self.conn.prepare("SELECT description, author, rule, created, tags from revision_meta
+            WHERE revision_id=?1;")?;
+        let mut rows = sel.query([&rev_id])?;
+        if let Some(row) = rows.next()? {
+            let tags: String = row.get(4)?;
+            meta.description = row.get(0)?;
+            meta.author = row.get(1)?;
+            meta.rule = row.get(2)?;
+            meta.created = row.get(3)?;
+            meta.tags = tags.split(',').filter(|t| !t.is_empty()).map(String::from).collect();
+            // The soup was archived along with the metadata, none means there was none
+            let mut sel = self.conn.prepare("SELECT seed, density, symmetry, x, y, width, height from revision_soups
+                WHERE revision_id=?1;")?;
+            let mut rows = sel.query([&rev_id])?;
+            soup = match rows.next()? {
+                Some(row) => Some(_soup_from_row(row)?),
+                None => None,
+            };
+        }
+        Ok((soup, meta))
+    }
+
+    /// Makes an old revision the current one, with its soup and metadata.
+    /// The current state is kept as a revision, so nothing is lost.
+    fn restore_revision(&mut self, name: &str, number: i64) -> Result<()> {
+        let cells: HashSet<(i32, i32)> = self.load_revision(name, number)?.into_iter().collect();
+        let (soup, meta) = self.load_revision_meta(name, number)?;
+        self.overwrite(name, &cells, soup.as_ref(), &meta)
+    }
+
+    /// Metadata of the record. Records saved before metadata existed get the
+    /// defaults, with population and bounding box computed from the cells.
+    fn load_meta(&self, name: &str) -> Result<RecordMeta> {
+        let mut sel = self.conn.prepare("SELECT m.description, m.author, m.rule, m.generation, m.population,
+            m.bbox_x, m.bbox_y, m.bbox_w, m.bbox_h, m.created, m.modified, m.tags
+            from record_meta m join records r on r.id = m.record_id WHERE r.name=?1;")?;
+        let mut rows = sel.query([name])?;
+        match rows.next()? {
+            Some(row) => {
+                let tags: String = row.get(11)?;
+                Ok(RecordMeta {
+                    description: row.get(0)?,
+                    author: row.get(1)?,
+                    rule: row.get(2)?,
+                    generation: row.get(3)?,
+                    population: row.get(4)?,
+                    bbox: (row.get(5)?, row.get(6)?, row.get(7)?, row.get(8)?),
+                    created: row.get(9)?,
+                    modified: row.get(10)?,
+                    tags: tags.split(',').filter(|t| !t.is_empty()).map(String::from).collect(),
+                })
+            }
+            None => {
+                let cells: HashSet<(i32, i32)> = self.load(name)?.into_iter().collect();
+                Ok(RecordMeta {
+                    population: cells.len() as i64,
+                    bbox: bbox(&cells),
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    fn load_soup(&self, name: &str) -> Result<Option<Soup>> {
+        let mut sel = self.conn.prepare("SELECT s.seed, s.density, s.symmetry, s.x, s.y, s.width, s.height
+            from soups s join records r on r.id = s.record_id WHERE r.name=?1;")?;
+        let mut rows = sel.query([name])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(_soup_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Soup from a row of (seed, density, symmetry, x, y, width, height)
+fn _soup_from_row(row: &rusqlite::Row) -> rusqlite::Result<Soup> {
+    let seed: i64 = row.get(0)?;
+    let symmetry: String = row.get(2)?;
+    Ok(Soup {
+        x: row.get(3)?,
+        y: row.get(4)?,
+        width: row.get(5)?,
+        height: row.get(6)?,
+        density: row.get(1)?,
+        symmetry: Symmetry::parse(&symmetry).unwrap_or(Symmetry::C1),
+        seed: seed as u64,
+    })
 }
 
 #[cfg(test)]
 
 #[test]
 fn test_save() {
-    let mut storage = Storage::new("test.db");
+    let mut storage = Storage::new(":memory:").unwrap();
     let mut cells: HashSet<(i32, i32)> = HashSet::new();
     cells.insert((1, 2));
     cells.insert((3, 4));
-    storage.save("test_cfg", &cells);
+    storage.save("test_cfg", &cells, None, &RecordMeta::default()).unwrap();
+    // Cells further apart than the longest run of the RLE reader
+    let far: HashSet<(i32, i32)> = [(0, 0), (2_000_000, 0)].into_iter().collect();
+    storage.save("far", &far, None, &RecordMeta::default()).unwrap();
+    let back: HashSet<(i32, i32)> = storage.load("far").unwrap().into_iter().collect();
+    assert_eq!(back, far);
+}
+
+#[test]
+fn test_save_soup() {
+    let mut storage = Storage::new(":memory:").unwrap();
+    let soup = Soup {
+        x: 0,
+        y: 0,
+        width: 8,
+        height: 8,
+        density: 0.375,
+        symmetry: Symmetry::C4,
+        seed: u64::MAX - 1,
+    };
+    storage.save("soup", &soup.generate(), Some(&soup), &RecordMeta::default()).unwrap();
+    storage.save("plain", &HashSet::new(), None, &RecordMeta::default()).unwrap();
+    assert_eq!(storage.load_soup("soup").unwrap(), Some(soup));
+    assert_eq!(storage.load_soup("plain").unwrap(), None);
+}
+
+#[test]
+fn test_save_meta() {
+    let mut storage = Storage::new(":memory:").unwrap();
+    let cells: HashSet<(i32, i32)> = [(-1, 2), (3, 4), (0, 0)].into_iter().collect();
+    let meta = RecordMeta {
+        description: String::from("Three cells"),
+        author: String::from("me"),
+        rule: String::from("B3/S23"),
+        generation: 12,
+        tags: vec![String::from("test"), String::from("small")],
+        ..Default::default()
+    };
+    storage.save("meta", &cells, None, &meta).unwrap();
+    let loaded = storage.load_meta("meta").unwrap();
+    assert_eq!(loaded.description, meta.description);
+    assert_eq!(loaded.tags, meta.tags);
+    assert_eq!(loaded.generation, 12);
+    assert_eq!(loaded.population, 3);
+    assert_eq!(loaded.bbox, (-1, 0, 5, 5));
+    assert!(loaded.created > 0 && loaded.created == loaded.modified);
+    assert_eq!(format_timestamp(0), "1970-01-01 00:00");
+    assert_eq!(format_timestamp(951782400 + 3660), "2000-02-29 01:01");
+}
+
+#[test]
+fn test_migrate_old_database() {
+    let fname = std::env::temp_dir().join(format!("lf-migrate-{}.db", std::process::id()));
+    let fname = fname.to_str().unwrap();
+    let backup = format!("{}.v0.bak", fname);
+    {
+        // The schema before versioning
+        let conn = Connection::open(fname).unwrap();
+        conn.execute_batch("create table records (id integer primary key, name text not null unique);
+            create table lcells (id integer primary key, record_id integer not null references records(id),
+                x integer not null, y integer not null);
+            insert into records (id, name) values (1, 'old');
+            insert into lcells (record_id, x, y) values (1, 5, 6);").unwrap();
+    }
+    let storage = Storage::new(fname).unwrap();
+    assert_eq!(storage.schema_version().unwrap(), MIGRATIONS.len());
+    assert_eq!(storage.load("old").unwrap(), vec![(5, 6)]);
+    assert_eq!(storage.load_meta("old").unwrap().population, 1);
+    assert!(std::path::Path::new(&backup).exists());
+    drop(storage);
+    // Up to date: opens without another backup
+    std::fs::remove_file(&backup).unwrap();
+    Storage::new(fname).unwrap();
+    assert!(!std::path::Path::new(&backup).exists());
+    std::fs::remove_file(fname).unwrap();
+}
+
+#[test]
+fn test_overwrite_rename_duplicate() {
+    let mut storage = Storage::new(":memory:").unwrap();
+    let mut cells: HashSet<(i32, i32)> = [(0, 0), (1, 0)].into_iter().collect();
+    storage.save("a", &cells, None, &RecordMeta::default()).unwrap();
+    assert!(storage.save("a", &cells, None, &RecordMeta::default()).is_err());
+    cells.insert((2, 0));
+    storage.overwrite("a", &cells, None, &RecordMeta::default()).unwrap();
+    assert_eq!(storage.load("a").unwrap().len(), 3);
+    assert_eq!(storage.load_meta("a").unwrap().population, 3);
+
+    storage.duplicate("a", "b").unwrap();
+    storage.rename("a", "c").unwrap();
+    assert!(!storage.exists("a").unwrap());
+    assert!(storage.rename("b", "c").is_err());
+    assert_eq!(storage.load("b").unwrap().len(), 3);
+    assert_eq!(storage.load("c").unwrap().len(), 3);
+    storage.delete("c").unwrap();
+    assert_eq!(storage.load("b").unwrap().len(), 3);
+}
+
+#[test]
+fn test_revisions() {
+    let mut storage = Storage::new(":memory:").unwrap();
+    let mut cells: HashSet<(i32, i32)> = [(0, 0)].into_iter().collect();
+    let highlife = RecordMeta { rule: "B36/S23".to_string(), ..Default::default() };
+    let soup = Soup { x: 0, y: 0, width: 4, height: 4, density: 0.5, symmetry: Symmetry::C1, seed: 3 };
+    storage.save("r", &cells, Some(&soup), &highlife).unwrap();
+    cells.insert((1, 1));
+    storage.overwrite("r", &cells, None, &RecordMeta { generation: 5, rule: "B3/S23".to_string(), ..Default::default() }).unwrap();
+    let revs = storage.revisions("r").unwrap();
+    assert_eq!(revs.iter().map(|r| r.population).collect::<Vec<i64>>(), vec![1, 2]);
+    assert_eq!(revs[1].generation, 5);
+    assert_eq!(storage.load_revision("r", 1).unwrap(), vec![(0, 0)]);
+
+    storage.restore_revision("r", 1).unwrap();
+    assert_eq!(storage.load("r").unwrap(), vec![(0, 0)]);
+    assert_eq!(storage.load_meta("r").unwrap().rule, "B36/S23");
+    assert_eq!(storage.load_soup("r").unwrap(), Some(soup));
+    let (old_soup, old_meta) = storage.load_revision_meta("r", 2).unwrap();
+    assert_eq!((old_soup, old_meta.rule.as_str(), old_meta.generation), (None, "B3/S23", 5));
+    let revs = storage.revisions("r").unwrap();
+    assert_eq!(revs.iter().map(|r| r.population).collect::<Vec<i64>>(), vec![1, 2, 1]);
+    assert_eq!(storage.load_revision("r", 2).unwrap().len(), 2);
+
+    storage.delete("r").unwrap();
+    let left: i64 = storage.conn.query_row("SELECT (select count(*) from revisions)
+        + (select count(*) from revision_meta) + (select count(*) from revision_soups)", [], |row| row.get(0)).unwrap();
+    assert_eq!(left, 0);
+}
+
+/// Timing of save and load of a 500k cell pattern:
+/// cargo test --release bench_large_pattern -- --ignored --nocapture
+#[test]
+#[ignore]
+fn bench_large_pattern() {
+    let soup = Soup {
+        x: 0,
+        y: 0,
+        width: 1000,
+        height: 1000,
+        density: 0.5,
+        symmetry: Symmetry::C1,
+        seed: 1,
+    };
+    let cells = soup.generate();
+    let mut storage = Storage::new(":memory:").unwrap();
+    let start = std::time::Instant::now();
+    storage.save("large", &cells, None, &RecordMeta::default()).unwrap();
+    let saved = start.elapsed();
+    let loaded = storage.load("large").unwrap();
+    let size: i64 = storage.conn.query_row("SELECT length(cells) from records", [], |row| row.get(0)).unwrap();
+    println!("{} cells: save {:?}, load {:?}, {} bytes", cells.len(), saved, start.elapsed() - saved, size);
+    assert_eq!(loaded.len(), cells.len());
+}
+
+#[test]
+fn test_duplicates() {
+    let mut storage = Storage::new(":memory:").unwrap();
+    let glider: HashSet<(i32, i32)> = patterns::get("Glider").unwrap().into_iter().collect();
+    let moved: HashSet<(i32, i32)> = glider.iter().map(|(x, y)| (x + 5, y - 3)).collect();
+    let turned: HashSet<(i32, i32)> = patterns::orient(&patterns::get("Glider").unwrap(), 2).into_iter().collect();
+    storage.save("glider", &glider, None, &RecordMeta::default()).unwrap();
+    storage.save("moved", &moved, None, &RecordMeta::default()).unwrap();
+    storage.save("turned", &turned, None, &RecordMeta::default()).unwrap();
+    storage.save("empty", &HashSet::new(), None, &RecordMeta::default()).unwrap();
+    storage.save("empty too", &HashSet::new(), None, &RecordMeta::default()).unwrap();
+    assert_eq!(storage.find_equivalent(&glider, false).unwrap(), vec!["glider", "moved"]);
+    assert_eq!(storage.find_equivalent(&glider, true).unwrap(), vec!["glider", "moved", "turned"]);
+    assert_eq!(storage.duplicates(false).unwrap(), vec![vec!["glider", "moved"]]);
+    assert_eq!(storage.duplicates(true).unwrap(), vec![vec!["glider", "moved", "turned"]]);
+    // A colliding hash is not enough to be a duplicate
+    let boat: HashSet<(i32, i32)> = patterns::get("Boat").unwrap().into_iter().collect();
+    storage.save("boat", &boat, None, &RecordMeta::default()).unwrap();
+    storage.conn.execute("update records set hash=(select hash from records where name='glider') where name='boat'", []).unwrap();
+    assert_eq!(storage.find_equivalent(&glider, false).unwrap(), vec!["glider", "moved"]);
+    assert_eq!(storage.duplicates(false).unwrap(), vec![vec!["glider", "moved"]]);
+}
+
+#[test]
+fn test_search() {
+    // Stretches rows to the right, enough to check the generations
+    fn grow(f: &mut HashSet<(i32, i32)>) {
+        let right: Vec<(i32, i32)> = f.iter().map(|(x, y)| (x + 1, *y)).collect();
+        f.extend(right);
+    }
+    let mut storage = Storage::new(":memory:").unwrap();
+    let glider = patterns::get("Glider").unwrap();
+    let mut gun: HashSet<(i32, i32)> = patterns::get("Gosper glider gun").unwrap().into_iter().collect();
+    storage.save("gun", &gun, None, &RecordMeta::default()).unwrap();
+    gun.extend(patterns::orient(&glider, 2).into_iter().map(|(x, y)| (x + 50, y + 50)));
+    storage.save("gun and glider", &gun, None, &RecordMeta::default()).unwrap();
+    let block: HashSet<(i32, i32)> = patterns::get("Block").unwrap().into_iter().collect();
+    storage.save("block", &block, None, &RecordMeta::default()).unwrap();
+    let found = storage.search(&patterns::get("Gosper glider gun").unwrap(), false, 0, &grow).unwrap();
+    let names: Vec<&str> = found.iter().map(|(n, _)| n.as_str()).collect();
+    assert_eq!(names, vec!["gun", "gun and glider"]);
+    assert!(storage.search(&glider, false, 0, &grow).unwrap().is_empty());
+    let found = storage.search(&glider, true, 0, &grow).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].1[0].x, found[0].1[0].y), (50, 50));
+    let found = storage.search(&[(0, 0), (1, 0), (0, 1), (1, 1)], false, 0, &grow).unwrap();
+    assert_eq!(found.len(), 3);
+    // A single cell grows into a blinker in two generations
+    storage.save("dot", &[(7, 7)].into_iter().collect(), None, &RecordMeta::default()).unwrap();
+    let found = storage.search(&patterns::get("Blinker").unwrap(), false, 5, &grow).unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].0.as_str(), found[0].1[0].generation, found[0].1[0].x), ("dot", 2, 7));
+}
+
+#[test]
+fn test_errors() {
+    let mut storage = Storage::new(":memory:").unwrap();
+    storage.save("a", &HashSet::new(), None, &RecordMeta::default()).unwrap();
+    storage.save("b", &HashSet::new(), None, &RecordMeta::default()).unwrap();
+    assert!(matches!(storage.load("c"), Err(Error::NotFound(n)) if n == "c"));
+    assert!(matches!(storage.delete("c"), Err(Error::NotFound(_))));
+    assert!(matches!(storage.save("a", &HashSet::new(), None, &RecordMeta::default()), Err(Error::DuplicateName(n)) if n == "a"));
+    assert!(matches!(storage.rename("a", "b"), Err(Error::DuplicateName(n)) if n == "b"));
+    assert!(matches!(storage.duplicate("a", "b"), Err(Error::DuplicateName(_))));
+    storage.conn.execute("update records set cells='2o$%!' where name='a'", []).unwrap();
+    assert!(matches!(storage.load("a"), Err(Error::Corrupted(_))));
+    // Not an SQLite file
+    let fname = std::env::temp_dir().join(format!("lf-garbage-{}.db", std::process::id()));
+    std::fs::write(&fname, "this is not a database, just some text long enough to have a header").unwrap();
+    assert!(matches!(Storage::new(fname.to_str().unwrap()), Err(Error::Corrupted(_))));
+    std::fs::remove_file(&fname).unwrap();
+    // Written by a later version
+    let fname = std::env::temp_dir().join(format!("lf-newer-{}.db", std::process::id()));
+    Connection::open(&fname).unwrap().execute_batch(&format!("PRAGMA user_version = {};", MIGRATIONS.len() + 1)).unwrap();
+    assert!(matches!(Storage::new(fname.to_str().unwrap()), Err(Error::TooNew(v)) if v == MIGRATIONS.len() + 1));
+    std::fs::remove_file(&fname).unwrap();
 }
@@ -0,0 +1,210 @@
+use std::collections::{HashSet, VecDeque};
+
+/// Drawing tools of the edit mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool {
+    Pencil,
+    Line,
+    Rect,
+    FilledRect,
+    Ellipse,
+    FilledEllipse,
+    Fill,
+}
+
+pub const ALL: [Tool; 7] = [
+    Tool::Pencil,
+    Tool::Line,
+    Tool::Rect,
+    Tool::FilledRect,
+    Tool::Ellipse,
+    Tool::FilledEllipse,
+    Tool::Fill,
+];
+
+impl Tool {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tool::Pencil => "pencil",
+            Tool::Line => "line",
+            Tool::Rect => "rectangle",
+            Tool::FilledRect => "filled rectangle",
+            Tool::Ellipse => "ellipse",
+            Tool::FilledEllipse => "filled ellipse",
+            Tool::Fill => "flood fill",
+        }
+    }
+
+    pub fn key(&self) -> char {
+        match self {
+            Tool::Pencil => 'p',
+            Tool::Line => 'l',
+            Tool::Rect => 'r',
+            Tool::FilledRect => 'R',
+            Tool::Ellipse => 'e',
+            Tool::FilledEllipse => 'E',
+            Tool::Fill => 'f',
+        }
+    }
+
+    pub fn from_key(c: char) -> Option<Tool> {
+        ALL.iter().find(|t| t.key() == c).copied()
+    }
+
+    /// Tools drawing a shape between two corners
+    pub fn is_shape(&self) -> bool {
+        !matches!(self, Tool::Pencil | Tool::Fill)
+    }
+
+    /// Cells of the shape spanned by the two points
+    pub fn shape(&self, a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+        match self {
+            Tool::Line => line(a, b),
+            Tool::Rect => rect(a, b, false),
+            Tool::FilledRect => rect(a, b, true),
+            Tool::Ellipse => ellipse(a, b, false),
+            Tool::FilledEllipse => ellipse(a, b, true),
+            Tool::Pencil | Tool::Fill => vec![b],
+        }
+    }
+}
+
+/// Bresenham's line
+pub fn line(a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+    let (mut x, mut y) = a;
+    let (dx, dy) = ((b.0 - x).abs(), -(b.1 - y).abs());
+    let (sx, sy) = ((b.0 - x).signum(), (b.1 - y).signum());
+    let mut err = dx + dy;
+    let mut res: Vec<(i32, i32)> = vec![];
+    loop {
+        res.push((x, y));
+        if (x, y) == b {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+    res
+}
+
+fn _bbox(a: (i32, i32), b: (i32, i32)) -> (i32, i32, i32, i32) {
+    (a.0.min(b.0), a.1.min(b.1), a.0.max(b.0), a.1.max(b.1))
+}
+
+pub fn rect(a: (i32, i32), b: (i32, i32), filled: bool) -> Vec<(i32, i32)> {
+    let (x0, y0, x1, y1) = _bbox(a, b);
+    let mut res: Vec<(i32, i32)> = vec![];
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            if filled || x == x0 || x == x1 || y == y0 || y == y1 {
+                res.push((x, y));
+            }
+        }
+    }
+    res
+}
+
+/// Ellipse inscribed in the rectangle spanned by the two points. The outline
+/// is made of the inner cells having a neighbour outside.
+pub fn ellipse(a: (i32, i32), b: (i32, i32), filled: bool) -> Vec<(i32, i32)> {
+    let (x0, y0, x1, y1) = _bbox(a, b);
+    let (w, h) = ((x1 - x0 + 1) as f64, (y1 - y0 + 1) as f64);
+    let (cx2, cy2) = (x0 + x1, y0 + y1);
+    let inside = |x: i32, y: i32| {
+        let u = (2 * x - cx2) as f64 / w;
+        let v = (2 * y - cy2) as f64 / h;
+        u * u + v * v <= 1.0
+    };
+    let mut res: Vec<(i32, i32)> = vec![];
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            if inside(x, y)
+                && (filled
+                    || !inside(x - 1, y)
+                    || !inside(x + 1, y)
+                    || !inside(x, y - 1)
+                    || !inside(x, y + 1))
+            {
+                res.push((x, y));
+            }
+        }
+    }
+    res
+}
+
+/// Dead cells 4-connected to the start cell. Returns None when the region is
+/// not enclosed, i.e. it leaks out of the bounding box of the live cells.
+pub fn flood_fill(field: &HashSet<(i32, i32)>, start: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+    if field.contains(&start) || field.is_empty() {
+        return None;
+    }
+    let x0 = field.iter().map(|c| c.0).min().unwrap();
+    let x1 = field.iter().map(|c| c.0).max().unwrap();
+    let y0 = field.iter().map(|c| c.1).min().unwrap();
+    let y1 = field.iter().map(|c| c.1).max().unwrap();
+    let mut seen: HashSet<(i32, i32)> = HashSet::new();
+    let mut queue: VecDeque<(i32, i32)> = VecDeque::new();
+    seen.insert(start);
+    queue.push_back(start);
+    while let Some((x, y)) = queue.pop_front() {
+        if x <= x0 || x >= x1 || y <= y0 || y >= y1 {
+            return None;
+        }
+        for n in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            if !field.contains(&n) && seen.insert(n) {
+                queue.push_back(n);
+            }
+        }
+    }
+    Some(seen.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        assert_eq!(line((0, 0), (3, 1)), vec![(0, 0), (1, 0), (2, 1), (3, 1)]);
+        assert_eq!(line((2, 2), (2, -1)).len(), 4);
+        assert_eq!(line((5, 5), (5, 5)), vec![(5, 5)]);
+    }
+
+    #[test]
+    fn test_rect() {
+        assert_eq!(rect((3, 2), (0, 0), false).len(), 10);
+        assert_eq!(rect((0, 0), (3, 2), true).len(), 12);
+        assert!(!rect((0, 0), (3, 2), false).contains(&(1, 1)));
+        assert_eq!(rect((1, 1), (1, 1), false), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn test_ellipse() {
+        let filled = ellipse((4, 4), (0, 0), true);
+        // The 5x5 square without its corners
+        assert_eq!(filled.len(), 21);
+        assert!(!filled.contains(&(0, 0)) && filled.contains(&(2, 0)));
+        let outline = ellipse((0, 0), (4, 4), false);
+        assert_eq!(outline.len(), 12);
+        assert!(outline.iter().all(|c| filled.contains(c)));
+        assert!(!outline.contains(&(2, 2)));
+        assert_eq!(ellipse((3, 3), (3, 3), false), vec![(3, 3)]);
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        let mut field: HashSet<(i32, i32)> = rect((0, 0), (4, 4), false).into_iter().collect();
+        assert_eq!(flood_fill(&field, (2, 2)).unwrap().len(), 9);
+        assert_eq!(flood_fill(&field, (7, 7)), None);
+        // Open the box
+        field.remove(&(4, 2));
+        assert_eq!(flood_fill(&field, (2, 2)), None);
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::HashSet;
+use std::fmt::write;
+use std::fs;
+use std::path::Path;
+
+use crate::bundle::{self, Conflict, ImportReport};
+use crate::db::{Error, RecordMeta, Result};
+use crate::macrocell::Tree;
+use crate::rle::MAX_CELLS;
+use crate::store::{self, PatternStore, Record};
+use crate::universe;
+
+/// Largest bounding box, in cells, written as rows of characters
+pub const MAX_AREA: i64 = 1 << 28;
+
+/// Pattern file formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Rle,
+    /// Plaintext: '.' and 'O' rows, '!' comments
+    Cells,
+    /// '#P' blocks of '.' and '*' rows
+    Life105,
+    /// One "x y" line per live cell
+    Life106,
+    /// Golly's hashed quadtree, see macrocell::Tree
+    Macrocell,
+}
+
+pub const ALL: [Format; 5] = [
+    Format::Rle,
+    Format::Cells,
+    Format::Life105,
+    Format::Life106,
+    Format::Macrocell,
+];
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Rle => "RLE",
+            Format::Cells => "Plaintext (.cells)",
+            Format::Life105 => "Life 1.05",
+            Format::Life106 => "Life 1.06",
+            Format::Macrocell => "Macrocell (.mc)",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Rle => "rle",
+            Format::Cells => "cells",
+            Format::Life105 | Format::Life106 => "lif",
+            Format::Macrocell => "mc",
+        }
+    }
+
+    /// From the extension of the file. Life 1.06 is written as .lif.
+    pub fn of(path: &Path) -> Option<Format> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "rle" => Some(Format::Rle),
+            "cells" => Some(Format::Cells),
+            "lif" | "life" => Some(Format::Life106),
+            "mc" => Some(Format::Macrocell),
+            _ => None,
+        }
+    }
+
+    /// Files of these extensions are picked when importing a directory
+    pub fn is_pattern_file(path: &Path) -> bool {
+        Format::of(path).is_some()
+    }
+}
+
+fn _is_cells_row(line: &str) -> bool {
+    line.chars().all(|c| matches!(c, '.' | 'O' | '*'))
+}
+
+fn _is_coordinates(line: &str) -> bool {
+    let nums: Vec<&str> = line.split_whitespace().collect();
+    nums.len() == 2 && nums.iter().all(|n| n.parse::<i32>().is_ok())
+}
+
+/// Guesses the format from the contents
+pub fn detect(text: &str) -> Format {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let first = lines.first().copied().unwrap_or("");
+    let body: Vec<&str> = lines
+        .iter()
+        .copied()
+        .filter(|l| !l.starts_with('#'))
+        .collect();
+    if first.starts_with("[M2]") {
+        Format::Macrocell
+    } else if first.starts_with("#Life 1.06") {
+        Format::Life106
+    } else if first.starts_with("#Life 1.05") || lines.iter().any(|l| l.starts_with("#P")) {
+        Format::Life105
+    } else if body
+        .iter()
+        .any(|l| l.starts_with("x ") || l.starts_with("x="))
+    {
+        Format::Rle
+    } else if first.starts_with('!') {
+        Format::Cells
+    } else if !body.is_empty() && body.iter().all(|l| _is_coordinates(l)) {
+        Format::Life106
+    } else if !body.is_empty() && body.iter().all(|l| _is_cells_row(l)) {
+        Format::Cells
+    } else {
+        Format::Rle
+    }
+}
+
+/// "B3/S23" from the "23/3" survival/birth notation of Life 1.05
+fn _rule_from_sb(sb: &str) -> String {
+    let (s, b) = sb.split_once('/').unwrap_or((sb, ""));
+    format!("B{}/S{}", b.trim(), s.trim())
+}
+
+fn _rule_to_sb(rule: &str) -> Option<String> {
+    let (b, s) = rule.split_once('/')?;
+    Some(format!(
+        "{}/{}",
+        s.strip_prefix(['S', 's'])?,
+        b.strip_prefix(['B', 'b'])?
+    ))
+}
+
+fn _parse_cells(text: &str) -> Result<Record> {
+    let mut meta = RecordMeta::default();
+    let mut description: Vec<&str> = vec![];
+    let mut cells: Vec<(i32, i32)> = vec![];
+    let mut y = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if let Some(comment) = line.strip_prefix('!') {
+            if !comment.starts_with("Name:") {
+                description.push(comment.trim());
+            }
+            continue;
+        }
+        for (x, c) in line.chars().enumerate() {
+            match c {
+                'O' | '*' => cells.push((x as i32, y)),
+                '.' => {}
+                c => {
+                    return Err(Error::Corrupted(format!(
+                        "Unexpected character '{}' in plaintext",
+                        c
+                    )))
+                }
+            }
+        }
+        y += 1;
+    }
+    meta.description = description.join("\n").trim().to_string();
+    Ok((cells, None, meta))
+}
+
+fn _too_many() -> Error {
+    Error::Corrupted(format!("More than {} live cells", MAX_CELLS))
+}
+
+fn _too_large() -> Error {
+    Error::Corrupted(String::from("Pattern too large"))
+}
+
+fn _parse_life105(text: &str) -> Result<Record> {
+    let mut meta = RecordMeta::default();
+    let mut description: Vec<&str> = vec![];
+    let mut cells: Vec<(i32, i32)> = vec![];
+    let (mut x0, mut y) = (0, 0);
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(d) = line.strip_prefix("#D").or_else(|| line.strip_prefix("#C")) {
+            description.push(d.trim());
+        } else if line.starts_with("#N") {
+            meta.rule = String::from("B3/S23");
+        } else if let Some(r) = line.strip_prefix("#R") {
+            meta.rule = _rule_from_sb(r.trim());
+        } else if let Some(p) = line.strip_prefix("#P") {
+            let v: Vec<i32> = p
+                .split_whitespace()
+                .filter_map(|n| n.parse().ok())
+                .collect();
+            if v.len() != 2 {
+                return Err(Error::Corrupted(format!("Bad block position '{}'", line)));
+            }
+            x0 = v[0];
+            y = v[1];
+        } else if line.starts_with('#') || line.is_empty() {
+            continue;
+        } else {
+            for (x, c) in line.chars().enumerate() {
+                match c {
+                    '*' | 'O' => {
+                        let x = i32::try_from(x)
+                            .ok()
+                            .and_then(|x| x0.checked_add(x))
+                            .ok_or_else(_too_large)?;
+                        if cells.len() >= MAX_CELLS {
+                            return Err(_too_many());
+                        }
+                        cells.push((x, y));
+                    }
+                    '.' => {}
+                    c => {
+                        return Err(Error::Corrupted(format!(
+                            "Unexpected character '{}' in Life 1.05",
+                            c
+                        )))
+                    }
+                }
+            }
+            y = y.checked_add(1).ok_or_else(_too_large)?;
+        }
+    }
+    meta.description = description.join("\n");
+    Ok((cells, None, meta))
+}
+
+fn _parse_life106(text: &str) -> Result<Record> {
+    let mut cells: Vec<(i32, i32)> = vec![];
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let v: Vec<i32> = line
+            .split_whitespace()
+            .filter_map(|n| n.parse().ok())
+            .collect();
+        if v.len() != 2 {
+            return Err(Error::Corrupted(format!(
+                "Bad coordinates '{}' in Life 1.06",
+                line
+            )));
+        }
+        if cells.len() >= MAX_CELLS {
+            return Err(_too_many());
+        }
+        cells.push((v[0], v[1]));
+    }
+    Ok((cells, None, RecordMeta::default()))
+}
+
+fn _parse_macrocell(text: &str) -> Result<Record> {
+    let tree = Tree::parse(text).map_err(Error::Corrupted)?;
+    let cells = tree.cells().map_err(Error::Corrupted)?;
+    let meta = RecordMeta {
+        description: tree.description,
+        rule: tree.rule,
+        generation: tree.generation,
+        ..Default::default()
+    };
+    Ok((cells.into_iter().collect(), None, meta))
+}
+
+/// Reads a pattern in the given format
+pub fn parse(text: &str, format: Format) -> Result<Record> {
+    let (cells, soup, meta) = match format {
+        Format::Rle => store::read_rle_file(text)?,
+        Format::Cells => _parse_cells(text)?,
+        Format::Life105 => _parse_life105(text)?,
+        Format::Life106 => _parse_life106(text)?,
+        Format::Macrocell => _parse_macrocell(text)?,
+    };
+    // Population and bounding box as for a saved record
+    let set: HashSet<(i32, i32)> = cells.iter().cloned().collect();
+    let (_, _, w, h) = universe::bbox(&set);
+    // Spans as the RLE reader allows, from 0 up to i32::MAX
+    if w == i32::MAX || h == i32::MAX {
+        return Err(_too_large());
+    }
+    let meta = RecordMeta {
+        population: set.len() as i64,
+        bbox: universe::bbox(&set),
+        ..meta
+    };
+    Ok((set.into_iter().collect(), soup, meta))
+}
+
+/// Rows of the bounding box, alive and dead characters; refused beyond
+/// MAX_AREA cells
+fn _rows(cells: &HashSet<(i32, i32)>, alive: char, dead: char, trim: bool) -> Result<Vec<String>> {
+    let (x0, y0, w, h) = universe::bbox(cells);
+    if w as i64 * h as i64 > MAX_AREA {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("A {}x{} pattern is too large to write as rows", w, h),
+        )));
+    }
+    let mut rows: Vec<Vec<char>> = vec![vec![dead; w as usize]; h as usize];
+    for (x, y) in cells.iter() {
+        rows[(y - y0) as usize][(x - x0) as usize] = alive;
+    }
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let s: String = r.into_iter().collect();
+            if trim {
+                let t = s.trim_end_matches(dead);
+                if t.is_empty() {
+                    dead.to_string()
+                } else {
+                    t.to_string()
+                }
+            } else {
+                s
+            }
+        })
+        .collect())
+}
+
+/// Writes the pattern in the given format. Plaintext does not keep the
+/// position, Life 1.06 keeps nothing but the cells. Plaintext and Life 1.05
+/// refuse bounding boxes beyond MAX_AREA.
+pub fn write_pattern(
+    format: Format,
+    name: &str,
+    cells: &HashSet<(i32, i32)>,
+    meta: &RecordMeta,
+) -> Result<String> {
+    let mut s = String::new();
+    match format {
+        Format::Rle => {
+            // The position comes from the cells, not from a stored record
+            let meta = RecordMeta {
+                bbox: universe::bbox(cells),
+                ..meta.clone()
+            };
+            s = store::write_rle_file(name, cells, None, &meta)
+        }
+        Format::Cells => {
+            write(&mut s, format_args!("!Name: {}\n", name)).unwrap();
+            for line in meta.description.lines() {
+                write(&mut s, format_args!("!{}\n", line)).unwrap();
+            }
+            for row in _rows(cells, 'O', '.', true)? {
+                s.push_str(&row);
+                s.push('\n');
+            }
+        }
+        Format::Life105 => {
+            s.push_str("#Life 1.05\n");
+            write(&mut s, format_args!("#D {}\n", name)).unwrap();
+            for line in meta.description.lines() {
+                write(&mut s, format_args!("#D {}\n", line)).unwrap();
+            }
+            match _rule_to_sb(&meta.rule) {
+                Some(sb) if sb != "23/3" => write(&mut s, format_args!("#R {}\n", sb)).unwrap(),
+                _ => s.push_str("#N\n"),
+            }
+            let (x0, y0, _, _) = universe::bbox(cells);
+            write(&mut s, format_args!("#P {} {}\n", x0, y0)).unwrap();
+            for row in _rows(cells, '*', '.', true)? {
+                s.push_str(&row);
+                s.push('\n');
+            }
+        }
+        Format::Life106 => {
+            s.push_str("#Life 1.06\n");
+            let mut list: Vec<&(i32, i32)> = cells.iter().collect();
+            list.sort_by_key(|(x, y)| (*y, *x));
+            for (x, y) in list {
+                write(&mut s, format_args!("{} {}\n", x, y)).unwrap();
+            }
+        }
+        Format::Macrocell => {
+            let mut tree = Tree::from_cells(cells);
+            tree.rule = meta.rule.clone();
+            tree.generation = meta.generation;
+            tree.description = meta.description.clone();
+            s = tree.write()
+        }
+    }
+    Ok(s)
+}
+
+/// Reads a pattern file of any of the formats
+pub fn read_file(path: &Path) -> Result<Record> {
+    let text = fs::read_to_string(path)?;
+    parse(&text, detect(&text))
+}
+
+/// Imports a pattern file, or all the pattern files of a directory, as
+/// records named after the files. Files that cannot be read are listed in
+/// the report and left out; a failure of the store undoes the import.
+pub fn import_path(
+    store: &mut dyn PatternStore,
+    path: &Path,
+    conflict: Conflict,
+) -> Result<ImportReport> {
+    let mut files: Vec<std::path::PathBuf> = vec![];
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            let p = entry?.path();
+            if p.is_file() && Format::is_pattern_file(&p) {
+                files.push(p);
+            }
+        }
+        files.sort();
+    } else {
+        files.push(path.to_path_buf());
+    }
+    let mut report = ImportReport::default();
+    let mut records: Vec<(String, Record)> = vec![];
+    for file in files {
+        let name = file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        match read_file(&file) {
+            Ok(record) => records.push((name, record)),
+            Err(e) => report.failed.push(format!("{}: {}", file.display(), e)),
+        }
+    }
+    store.atomically(&mut |store| {
+        for (name, record) in std::mem::take(&mut records) {
+            bundle::add(store, &name, record, conflict, &mut report)?;
+        }
+        Ok(())
+    })?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patterns;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn test_formats_round_trip() {
+        let glider: HashSet<(i32, i32)> = patterns::get("Glider")
+            .unwrap()
+            .into_iter()
+            .map(|(x, y)| (x - 3, y + 4))
+            .collect();
+        let meta = RecordMeta {
+            description: String::from("A small ship"),
+            rule: String::from("B36/S23"),
+            ..Default::default()
+        };
+        for format in ALL {
+            let text = write_pattern(format, "glider", &glider, &meta).unwrap();
+            assert_eq!(detect(&text), format, "{}", text);
+            let (cells, _, m) = parse(&text, format).unwrap();
+            let cells: HashSet<(i32, i32)> = cells.into_iter().collect();
+            if format == Format::Cells {
+                let moved: HashSet<(i32, i32)> =
+                    glider.iter().map(|(x, y)| (x + 3, y - 4)).collect();
+                assert_eq!(cells, moved);
+            } else {
+                assert_eq!(cells, glider, "{}", format.name());
+            }
+            if format == Format::Life105 {
+                assert!(text.contains("#R 23/36"));
+                assert_eq!(m.rule, "B36/S23");
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("!Name: Blinker\nOOO\n"), Format::Cells);
+        assert_eq!(detect(".O.\n..O\nOOO\n"), Format::Cells);
+        assert_eq!(detect("0 0\n1 0\n2 0\n"), Format::Life106);
+        assert_eq!(detect("#N Glider\nbo$2bo$3o!"), Format::Rle);
+        assert_eq!(detect("#D Blinker\n#P -1 0\n***\n"), Format::Life105);
+        assert!(parse("#Life 1.06\n0 x\n", Format::Life106).is_err());
+        // Untrusted files: no overflow
+        assert!(parse("#P 2147483647 0\n..*\n", Format::Life105).is_err());
+        let wide = "#Life 1.06\n-2147483648 0\n2147483647 0\n";
+        assert!(parse(wide, Format::Life106).is_err());
+    }
+
+    #[test]
+    fn test_write_large() {
+        let far: HashSet<(i32, i32)> = [(0, 0), (100_000, 100_000)].into_iter().collect();
+        let meta = RecordMeta::default();
+        assert!(write_pattern(Format::Cells, "far", &far, &meta).is_err());
+        assert!(write_pattern(Format::Life105, "far", &far, &meta).is_err());
+        let text = write_pattern(Format::Life106, "far", &far, &meta).unwrap();
+        assert_eq!(parse(&text, Format::Life106).unwrap().0.len(), 2);
+    }
+
+    #[test]
+    fn test_import_dir() {
+        let dir = std::env::temp_dir().join(format!("lf-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("blinker.cells"), "!Name: Blinker\nOOO\n").unwrap();
+        fs::write(dir.join("block.lif"), "#Life 1.06\n0 0\n1 0\n0 1\n1 1\n").unwrap();
+        fs::write(dir.join("glider.rle"), "x = 3, y = 3\nbo$2bo$3o!\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a pattern").unwrap();
+        fs::write(dir.join("broken.rle"), "x = 3, y = 3\nbo$2bo$3%!\n").unwrap();
+        let mut store = MemoryStore::new();
+        let report = import_path(&mut store, &dir, Conflict::Skip).unwrap();
+        assert_eq!(report.added, 3);
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.failed[0].contains("broken.rle"));
+        assert_eq!(store.list().unwrap(), vec!["blinker", "block", "glider"]);
+        assert_eq!(store.load_meta("block").unwrap().population, 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
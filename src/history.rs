@@ -0,0 +1,229 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::universe;
+
+/// Oldest samples are dropped beyond that
+pub const MAX_SAMPLES: usize = 100_000;
+
+/// Measures of the field at one generation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub generation: i64,
+    pub population: usize,
+    pub bbox_area: u64,
+    /// Cells born and dead since the previous generation
+    pub births: usize,
+    pub deaths: usize,
+}
+
+impl Sample {
+    /// Measures `cells` alone, as the first sample of a run
+    pub fn of(generation: i64, cells: &HashSet<(i32, i32)>) -> Sample {
+        let (_, _, w, h) = universe::bbox(cells);
+        Sample {
+            generation,
+            population: cells.len(),
+            bbox_area: w as u64 * h as u64,
+            births: 0,
+            deaths: 0,
+        }
+    }
+
+    /// Measures `cells` after a step, given the numbers of cells born
+    /// and dead in it
+    pub fn stepped(
+        generation: i64,
+        cells: &HashSet<(i32, i32)>,
+        (births, deaths): (usize, usize),
+    ) -> Sample {
+        Sample {
+            births,
+            deaths,
+            ..Sample::of(generation, cells)
+        }
+    }
+}
+
+/// What the graph shows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Series {
+    Population,
+    BboxArea,
+    Births,
+    Deaths,
+}
+
+impl Series {
+    pub const ALL: [Series; 4] = [
+        Series::Population,
+        Series::BboxArea,
+        Series::Births,
+        Series::Deaths,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Series::Population => "Population",
+            Series::BboxArea => "Bounding box area",
+            Series::Births => "Births",
+            Series::Deaths => "Deaths",
+        }
+    }
+
+    pub fn value(self, s: &Sample) -> f64 {
+        match self {
+            Series::Population => s.population as f64,
+            Series::BboxArea => s.bbox_area as f64,
+            Series::Births => s.births as f64,
+            Series::Deaths => s.deaths as f64,
+        }
+    }
+}
+
+/// Samples of the generations run so far, in increasing generations
+#[derive(Default)]
+pub struct History {
+    samples: VecDeque<Sample>,
+}
+
+impl History {
+    pub fn samples(&self) -> &VecDeque<Sample> {
+        &self.samples
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Adds the sample; samples of the same or later generations, left from
+    /// a run the field went back from, are dropped.
+    pub fn push(&mut self, sample: Sample) {
+        while self
+            .samples
+            .back()
+            .is_some_and(|s| s.generation >= sample.generation)
+        {
+            self.samples.pop_back();
+        }
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Makes `cells` the starting point of the next steps, unless the
+    /// history already ends at that generation.
+    pub fn begin(&mut self, generation: i64, cells: &HashSet<(i32, i32)>) {
+        if self.samples.back().map(|s| s.generation) != Some(generation) {
+            self.push(Sample::of(generation, cells));
+        }
+    }
+
+    pub fn write_csv(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "generation,population,bbox_area,births,deaths")?;
+        for s in self.samples.iter() {
+            writeln!(
+                out,
+                "{},{},{},{},{}",
+                s.generation, s.population, s.bbox_area, s.births, s.deaths
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn save_csv(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_csv(&mut out)?;
+        out.flush()
+    }
+}
+
+/// Bit of the Braille dot at (column, row) of a character, out of 2 x 4
+fn _dot(col: usize, row: usize) -> u32 {
+    match (col, row) {
+        (0, 3) => 0x40,
+        (1, 3) => 0x80,
+        (0, r) => 1 << r,
+        (_, r) => 8 << r,
+    }
+}
+
+/// Line plot of `values` scaled from 0 to `top`, as `height` rows of
+/// `width` Braille characters. When there are more values than dot
+/// columns, each column spans the lowest to the highest of its values.
+pub fn plot(values: &[f64], top: f64, width: usize, height: usize) -> Vec<String> {
+    let (dots_w, dots_h) = (width * 2, height * 4);
+    let mut grid = vec![0u32; width * height];
+    if !values.is_empty() && dots_h > 0 {
+        let top = if top > 0.0 { top } else { 1.0 };
+        let level = |v: f64| ((v / top).clamp(0.0, 1.0) * (dots_h - 1) as f64).round() as usize;
+        let n = values.len();
+        let mut last: Option<usize> = None;
+        for c in 0..dots_w {
+            let from = c * n / dots_w;
+            let to = ((c + 1) * n / dots_w).max(from + 1);
+            let levels = values[from..to].iter().map(|v| level(*v));
+            let (mut lo, mut hi) = (levels.clone().min().unwrap(), levels.max().unwrap());
+            // Joined to the previous column
+            if let Some(l) = last {
+                lo = lo.min(l);
+                hi = hi.max(l);
+            }
+            last = Some(level(values[to - 1]));
+            for y in lo..=hi {
+                let row = dots_h - 1 - y;
+                grid[(row / 4) * width + c / 2] |= _dot(c % 2, row % 4);
+            }
+        }
+    }
+    grid.chunks(width.max(1))
+        .take(height)
+        .map(|row| {
+            row.iter()
+                .map(|bits| char::from_u32(0x2800 + bits).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let blinker: HashSet<(i32, i32)> = [(0, 0), (1, 0), (2, 0)].into_iter().collect();
+        let mut next = blinker.clone();
+        let counts = crate::rule::LIFE.step_counted(&mut next);
+        let mut h = History::default();
+        h.begin(0, &blinker);
+        h.push(Sample::stepped(1, &next, counts));
+        h.begin(1, &next);
+        assert_eq!(h.samples().len(), 2);
+        assert_eq!(
+            h.samples()[1],
+            Sample {
+                generation: 1,
+                population: 3,
+                bbox_area: 3,
+                births: 2,
+                deaths: 2
+            }
+        );
+        let mut csv = vec![];
+        h.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "generation,population,bbox_area,births,deaths\n0,3,3,0,0\n1,3,3,2,2\n"
+        );
+        // Going back drops the later generations
+        h.begin(0, &blinker);
+        assert_eq!(h.samples().len(), 1);
+
+        assert_eq!(plot(&[0.0, 1.0], 1.0, 1, 1), vec!["\u{28f8}"]);
+        assert_eq!(plot(&[], 1.0, 2, 1), vec!["\u{2800}\u{2800}"]);
+    }
+}
@@ -0,0 +1,23 @@
+//! Conway's Game of Life and other B/S rules on an unbounded plane.
+//!
+//! The simulator (`universe`, `rule`), the pattern formats (`rle`,
+//! `formats`, `macrocell`, `bundle`) and the pattern archives (`store`,
+//! `db`) can be used without the terminal UI of the life-cursive binary.
+
+pub mod bundle;
+pub mod cast;
+pub mod db;
+pub mod draw;
+pub mod formats;
+pub mod history;
+pub mod macrocell;
+pub mod patterns;
+pub mod render;
+pub mod rle;
+pub mod rule;
+pub mod search;
+pub mod soup;
+pub mod store;
+pub mod symmetry;
+pub mod trace;
+pub mod universe;
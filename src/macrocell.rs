@@ -0,0 +1,617 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::write;
+
+use crate::rule::Rule;
+
+/// Larger trees are not expanded into cells. Patterns that only fit as a
+/// quadtree (the Caterpillar, metapixel arrays) are run with `advance` and
+/// shown through `cells_in` instead.
+pub const MAX_CELLS: u64 = 20_000_000;
+
+/// Deepest tree read, 2^60 cells on a side
+pub const MAX_LEVEL: usize = 60;
+
+/// Beyond that many nodes, those of past generations are dropped
+const MAX_NODES: usize = 1 << 22;
+
+/// Node of a hashed quadtree. Level 3 nodes are 8x8 leaves, bit y*8+x;
+/// the others are squares of 2^level cells split in four quadrants
+/// nw, ne, sw, se of the level below. Index 0 is the empty node of any level.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Node {
+    Leaf(u64),
+    Inner(u32, [usize; 4]),
+}
+
+/// Pattern in Golly's macrocell format. Identical subtrees are stored once,
+/// so that the file and the tree stay small for huge regular patterns.
+/// Small trees are expanded into the cells of the field; any tree can also
+/// be run as it is, with Gosper's HashLife.
+#[derive(Clone, Debug)]
+pub struct Tree {
+    /// nodes[0] stands for the empty node
+    nodes: Vec<Node>,
+    index: HashMap<Node, usize>,
+    pub root: usize,
+    pub rule: String,
+    pub generation: i64,
+    pub description: String,
+    // Centres of nodes 2^j generations later, by node and j, for memo_rule
+    memo: HashMap<(usize, u32), usize>,
+    memo_rule: Option<Rule>,
+}
+
+impl Tree {
+    fn new() -> Tree {
+        Tree {
+            nodes: vec![Node::Leaf(0)],
+            index: HashMap::new(),
+            root: 0,
+            rule: String::new(),
+            generation: 0,
+            description: String::new(),
+            memo: HashMap::new(),
+            memo_rule: None,
+        }
+    }
+
+    /// Index of the node, shared with an identical one if any
+    fn add(&mut self, node: Node) -> usize {
+        match node {
+            Node::Leaf(0) => return 0,
+            Node::Inner(_, [0, 0, 0, 0]) => return 0,
+            _ => {}
+        }
+        if let Some(&i) = self.index.get(&node) {
+            return i;
+        }
+        self.nodes.push(node.clone());
+        self.index.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    fn level_of(&self, i: usize) -> Option<u32> {
+        match self.nodes[i] {
+            _ if i == 0 => None,
+            Node::Leaf(_) => Some(3),
+            Node::Inner(level, _) => Some(level),
+        }
+    }
+
+    /// Level of the root, the tree covers 2^level x 2^level cells
+    pub fn level(&self) -> u32 {
+        self.level_of(self.root).unwrap_or(3)
+    }
+
+    /// Number of live cells, counted without expanding the tree; u64::MAX
+    /// for trees holding more.
+    pub fn population(&self) -> u64 {
+        // Children always come before their parents
+        let mut pop: Vec<u64> = vec![0; self.nodes.len()];
+        for i in 1..self.nodes.len() {
+            pop[i] = match &self.nodes[i] {
+                Node::Leaf(bits) => bits.count_ones() as u64,
+                Node::Inner(_, q) => q.iter().fold(0u64, |n, &c| n.saturating_add(pop[c])),
+            };
+        }
+        pop[self.root]
+    }
+
+    fn _expand(&self, i: usize, x0: i64, y0: i64, out: &mut HashSet<(i32, i32)>) {
+        match &self.nodes[i] {
+            _ if i == 0 => {}
+            Node::Leaf(bits) => {
+                for b in 0..64 {
+                    if bits & (1 << b) != 0 {
+                        out.insert(((x0 + b % 8) as i32, (y0 + b / 8) as i32));
+                    }
+                }
+            }
+            Node::Inner(level, q) => {
+                let half = 1i64 << (level - 1);
+                self._expand(q[0], x0, y0, out);
+                self._expand(q[1], x0 + half, y0, out);
+                self._expand(q[2], x0, y0 + half, out);
+                self._expand(q[3], x0 + half, y0 + half, out);
+            }
+        }
+    }
+
+    /// Live cells. As in Golly the root is centred on the origin.
+    pub fn cells(&self) -> Result<HashSet<(i32, i32)>, String> {
+        if self.level() > 32 {
+            return Err(format!(
+                "a tree of level {} is too wide for the field",
+                self.level()
+            ));
+        }
+        let population = self.population();
+        if population > MAX_CELLS {
+            return Err(format!(
+                "{} cells, more than the {} the field can hold",
+                population, MAX_CELLS
+            ));
+        }
+        let mut out: HashSet<(i32, i32)> = HashSet::with_capacity(population as usize);
+        let half = 1i64 << (self.level() - 1);
+        self._expand(self.root, -half, -half, &mut out);
+        Ok(out)
+    }
+
+    fn _expand_in(
+        &self,
+        i: usize,
+        level: u32,
+        (x0, y0): (i128, i128),
+        region: (i32, i32, i32, i32),
+        out: &mut HashSet<(i32, i32)>,
+    ) {
+        let size = 1i128 << level;
+        let (rx, ry) = (region.0 as i128, region.1 as i128);
+        let (rx1, ry1) = (rx + region.2 as i128, ry + region.3 as i128);
+        if i == 0 || x0 >= rx1 || y0 >= ry1 || x0 + size <= rx || y0 + size <= ry {
+            return;
+        }
+        match &self.nodes[i] {
+            Node::Leaf(bits) => {
+                for b in 0..64 {
+                    let (x, y) = (x0 + b % 8, y0 + b / 8);
+                    if bits & (1 << b) != 0 && (rx..rx1).contains(&x) && (ry..ry1).contains(&y) {
+                        out.insert((x as i32, y as i32));
+                    }
+                }
+            }
+            Node::Inner(_, q) => {
+                let half = size / 2;
+                self._expand_in(q[0], level - 1, (x0, y0), region, out);
+                self._expand_in(q[1], level - 1, (x0 + half, y0), region, out);
+                self._expand_in(q[2], level - 1, (x0, y0 + half), region, out);
+                self._expand_in(q[3], level - 1, (x0 + half, y0 + half), region, out);
+            }
+        }
+    }
+
+    /// Live cells in the region (x, y, width, height), whatever the size of
+    /// the tree: the nodes outside are not expanded.
+    pub fn cells_in(&self, region: (i32, i32, i32, i32)) -> HashSet<(i32, i32)> {
+        let mut out = HashSet::new();
+        let half = 1i128 << (self.level() - 1);
+        self._expand_in(self.root, self.level(), (-half, -half), region, &mut out);
+        out
+    }
+
+    fn _quads(&self, i: usize) -> [usize; 4] {
+        match self.nodes[i] {
+            _ if i == 0 => [0; 4],
+            Node::Inner(_, q) => q,
+            Node::Leaf(_) => unreachable!("leaves are not split"),
+        }
+    }
+
+    fn _join(&mut self, level: u32, q: [usize; 4]) -> usize {
+        self.add(Node::Inner(level, q))
+    }
+
+    /// Node of the level above with `i` in its centre
+    fn _centre(&mut self, i: usize, level: u32) -> usize {
+        let [nw, ne, sw, se] = self._quads(i);
+        let q = [
+            self._join(level, [0, 0, 0, nw]),
+            self._join(level, [0, 0, ne, 0]),
+            self._join(level, [0, sw, 0, 0]),
+            self._join(level, [se, 0, 0, 0]),
+        ];
+        self._join(level + 1, q)
+    }
+
+    /// Centre of `i`, one level down, if there is nothing around it
+    fn _crop(&mut self, i: usize, level: u32) -> Option<usize> {
+        let [a, b, c, d] = self._quads(i).map(|n| self._quads(n));
+        let around = [
+            a[0], a[1], a[2], b[0], b[1], b[3], c[0], c[2], c[3], d[1], d[2], d[3],
+        ];
+        if around.iter().any(|&n| n != 0) {
+            return None;
+        }
+        Some(self._join(level - 1, [a[3], b[2], c[1], d[0]]))
+    }
+
+    /// Centre of the small node `i` run cell by cell
+    fn _run_cells(&mut self, i: usize, level: u32, j: u32, rule: &Rule) -> usize {
+        let mut cells = HashSet::new();
+        self._expand(i, 0, 0, &mut cells);
+        for _ in 0..1 << j {
+            rule.step(&mut cells);
+        }
+        let quarter = 1i64 << (level - 2);
+        let centre = quarter..3 * quarter;
+        let list: Vec<(i64, i64)> = cells
+            .into_iter()
+            .map(|(x, y)| (x as i64, y as i64))
+            .filter(|(x, y)| centre.contains(x) && centre.contains(y))
+            .collect();
+        self._build(level - 1, quarter, quarter, list)
+    }
+
+    /// Centre of node `i`, of half its size, 2^j generations later; j is at
+    /// most level - 2, as far as the edges of the node can reach.
+    fn _successor(&mut self, i: usize, level: u32, j: u32, rule: &Rule) -> usize {
+        if i == 0 {
+            return 0;
+        }
+        if let Some(&r) = self.memo.get(&(i, j)) {
+            return r;
+        }
+        let r = if level <= 5 {
+            self._run_cells(i, level, j, rule)
+        } else {
+            let q = self._quads(i);
+            let [a, b, c, d] = q.map(|n| self._quads(n));
+            let k = level - 1;
+            // Nine overlapping nodes of the level below, run as far as they can
+            let nine = [
+                q[0],
+                self._join(k, [a[1], b[0], a[3], b[2]]),
+                q[1],
+                self._join(k, [a[2], a[3], c[0], c[1]]),
+                self._join(k, [a[3], b[2], c[1], d[0]]),
+                self._join(k, [b[2], b[3], d[0], d[1]]),
+                q[2],
+                self._join(k, [c[1], d[0], c[3], d[2]]),
+                q[3],
+            ];
+            let step = j.min(level - 3);
+            let s = nine.map(|n| self._successor(n, k, step, rule));
+            let four = [
+                [s[0], s[1], s[3], s[4]],
+                [s[1], s[2], s[4], s[5]],
+                [s[3], s[4], s[6], s[7]],
+                [s[4], s[5], s[7], s[8]],
+            ];
+            let q = if step == j {
+                // Far enough: the centres of the four
+                four.map(|f| {
+                    let [w, x, y, z] = f.map(|n| self._quads(n));
+                    self._join(level - 2, [w[3], x[2], y[1], z[0]])
+                })
+            } else {
+                four.map(|f| {
+                    let n = self._join(k, f);
+                    self._successor(n, k, step, rule)
+                })
+            };
+            self._join(k, q)
+        };
+        self.memo.insert((i, j), r);
+        r
+    }
+
+    /// Runs the pattern for the generations with the rule, without
+    /// expanding it: identical regions are computed once (HashLife).
+    pub fn advance(&mut self, rule: &Rule, generations: u64) {
+        if self.memo_rule != Some(*rule) {
+            self.memo.clear();
+            self.memo_rule = Some(*rule);
+        }
+        if self.level() < 4 {
+            // A single leaf, centred in a node that can be split
+            let cells = self.cells_in((-4, -4, 8, 8));
+            let list = cells.iter().map(|&(x, y)| (x as i64, y as i64)).collect();
+            self.root = self._build(4, -8, -8, list);
+        }
+        for j in 0..64 {
+            if generations >> j & 1 == 0 || self.root == 0 {
+                continue;
+            }
+            // Room for the pattern to grow on every side, the result being
+            // the centre of the root
+            let mut level = self.level();
+            for _ in 0..2 {
+                self.root = self._centre(self.root, level);
+                level += 1;
+            }
+            while level < j + 3 {
+                self.root = self._centre(self.root, level);
+                level += 1;
+            }
+            self.root = self._successor(self.root, level, j, rule);
+            level -= 1;
+            while level > 5 {
+                match self._crop(self.root, level) {
+                    Some(r) => {
+                        self.root = r;
+                        level -= 1;
+                    }
+                    None => break,
+                }
+            }
+        }
+        self.generation += generations as i64;
+        if self.nodes.len() > MAX_NODES {
+            *self = self._compacted();
+        }
+    }
+
+    fn _copy(&mut self, from: &Tree, i: usize, copied: &mut HashMap<usize, usize>) -> usize {
+        if i == 0 {
+            return 0;
+        }
+        if let Some(&n) = copied.get(&i) {
+            return n;
+        }
+        let n = match &from.nodes[i] {
+            Node::Leaf(bits) => self.add(Node::Leaf(*bits)),
+            Node::Inner(level, q) => {
+                let q = q.map(|c| self._copy(from, c, copied));
+                self.add(Node::Inner(*level, q))
+            }
+        };
+        copied.insert(i, n);
+        n
+    }
+
+    /// The same pattern with only its own nodes, children first
+    fn _compacted(&self) -> Tree {
+        let mut tree = Tree {
+            rule: self.rule.clone(),
+            generation: self.generation,
+            description: self.description.clone(),
+            ..Tree::new()
+        };
+        tree.root = tree._copy(self, self.root, &mut HashMap::new());
+        tree
+    }
+
+    fn _build(&mut self, level: u32, x0: i64, y0: i64, cells: Vec<(i64, i64)>) -> usize {
+        if cells.is_empty() {
+            return 0;
+        }
+        if level == 3 {
+            let bits = cells
+                .iter()
+                .fold(0u64, |bits, (x, y)| bits | 1 << ((y - y0) * 8 + x - x0));
+            return self.add(Node::Leaf(bits));
+        }
+        let half = 1i64 << (level - 1);
+        let mut quadrants: [Vec<(i64, i64)>; 4] = Default::default();
+        for (x, y) in cells {
+            let q = (x >= x0 + half) as usize + 2 * (y >= y0 + half) as usize;
+            quadrants[q].push((x, y));
+        }
+        let [nw, ne, sw, se] = quadrants;
+        let q = [
+            self._build(level - 1, x0, y0, nw),
+            self._build(level - 1, x0 + half, y0, ne),
+            self._build(level - 1, x0, y0 + half, sw),
+            self._build(level - 1, x0 + half, y0 + half, se),
+        ];
+        self.add(Node::Inner(level, q))
+    }
+
+    /// Smallest tree centred on the origin holding the cells
+    pub fn from_cells(cells: &HashSet<(i32, i32)>) -> Tree {
+        let mut tree = Tree::new();
+        let mut level = 3;
+        let fits = |level: u32| {
+            let half = 1i64 << (level - 1);
+            cells.iter().all(|&(x, y)| {
+                (-half..half).contains(&(x as i64)) && (-half..half).contains(&(y as i64))
+            })
+        };
+        while !fits(level) {
+            level += 1;
+        }
+        let half = 1i64 << (level - 1);
+        let list: Vec<(i64, i64)> = cells.iter().map(|&(x, y)| (x as i64, y as i64)).collect();
+        tree.root = tree._build(level, -half, -half, list);
+        tree
+    }
+
+    fn _parse_leaf(line: &str) -> Result<u64, String> {
+        let (mut x, mut y, mut bits) = (0, 0, 0u64);
+        for c in line.chars() {
+            match c {
+                '$' => {
+                    x = 0;
+                    y += 1;
+                }
+                '.' | '*' if x < 8 && y < 8 => {
+                    if c == '*' {
+                        bits |= 1 << (y * 8 + x);
+                    }
+                    x += 1;
+                }
+                _ => return Err(format!("bad leaf '{}'", line)),
+            }
+        }
+        Ok(bits)
+    }
+
+    /// Reads a two-state [M2] file
+    pub fn parse(text: &str) -> Result<Tree, String> {
+        let mut lines = text.lines();
+        if !lines.next().unwrap_or("").starts_with("[M2]") {
+            return Err(String::from("no [M2] header"));
+        }
+        let mut tree = Tree::new();
+        let mut description: Vec<&str> = vec![];
+        for line in lines.map(str::trim).filter(|l| !l.is_empty()) {
+            if let Some(rule) = line.strip_prefix("#R") {
+                tree.rule = rule.trim().to_string();
+            } else if let Some(gen) = line.strip_prefix("#G") {
+                tree.generation = gen.trim().parse().map_err(|_| format!("bad '{}'", line))?;
+            } else if let Some(c) = line.strip_prefix("#C").or_else(|| line.strip_prefix("#D")) {
+                description.push(c.trim());
+            } else if line.starts_with('#') {
+                continue;
+            } else if line.starts_with(['.', '*', '$']) {
+                // Numbering counts every node line, even a repeated one
+                let bits = Tree::_parse_leaf(line)?;
+                tree.nodes.push(Node::Leaf(bits));
+            } else {
+                let v: Vec<usize> = line
+                    .split_whitespace()
+                    .map(|n| n.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| format!("bad node '{}'", line))?;
+                if v.len() != 5 {
+                    return Err(format!("bad node '{}'", line));
+                }
+                if v[0] < 4 {
+                    return Err(String::from("multi-state patterns are not supported"));
+                }
+                if v[0] > MAX_LEVEL {
+                    return Err(format!(
+                        "trees deeper than level {} are not supported",
+                        MAX_LEVEL
+                    ));
+                }
+                let level = v[0] as u32;
+                let q = [v[1], v[2], v[3], v[4]];
+                for &c in q.iter() {
+                    if c >= tree.nodes.len() || tree.level_of(c).is_some_and(|l| l != level - 1) {
+                        return Err(format!("bad child {} in '{}'", c, line));
+                    }
+                }
+                tree.nodes.push(Node::Inner(level, q));
+            }
+        }
+        for (i, node) in tree.nodes.iter().enumerate().skip(1) {
+            tree.index.entry(node.clone()).or_insert(i);
+        }
+        tree.root = tree.nodes.len() - 1;
+        tree.description = description.join("\n");
+        Ok(tree)
+    }
+
+    /// The file, with the nodes in the order of the tree
+    pub fn write(&self) -> String {
+        // Left over from runs, the root is no longer the last node
+        let tree = self._compacted();
+        let mut s = String::from("[M2] (life-cursive)\n");
+        if !self.rule.is_empty() {
+            write(&mut s, format_args!("#R {}\n", self.rule)).unwrap();
+        }
+        if self.generation != 0 {
+            write(&mut s, format_args!("#G {}\n", self.generation)).unwrap();
+        }
+        for line in self.description.lines() {
+            write(&mut s, format_args!("#C {}\n", line)).unwrap();
+        }
+        for node in tree.nodes.iter().skip(1) {
+            match node {
+                Node::Leaf(bits) => {
+                    let rows: Vec<String> = (0..8)
+                        .map(|y| {
+                            let row: String = (0..8)
+                                .map(|x| {
+                                    if bits & (1 << (y * 8 + x)) != 0 {
+                                        '*'
+                                    } else {
+                                        '.'
+                                    }
+                                })
+                                .collect();
+                            row.trim_end_matches('.').to_string()
+                        })
+                        .collect();
+                    s.push_str(rows.join("$").trim_end_matches('$'));
+                    s.push_str("$\n");
+                }
+                Node::Inner(level, q) => {
+                    write(
+                        &mut s,
+                        format_args!("{} {} {} {} {}\n", level, q[0], q[1], q[2], q[3]),
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macrocell() {
+        // A row of blocks repeats the same leaf
+        let mut cells: HashSet<(i32, i32)> = HashSet::new();
+        for i in 0..40 {
+            for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                cells.insert((i * 8 + x - 100, y + 3));
+            }
+        }
+        let mut tree = Tree::from_cells(&cells);
+        assert_eq!(tree.population(), 160);
+        assert!(tree.nodes.len() < 40);
+        tree.rule = String::from("B3/S23");
+        tree.generation = 12;
+        let text = tree.write();
+        let back = Tree::parse(&text).unwrap();
+        assert_eq!(back.write(), text);
+        assert_eq!(back.cells().unwrap(), cells);
+        assert_eq!((back.rule.as_str(), back.generation), ("B3/S23", 12));
+        // As written by Golly: the root is centred on the origin
+        let golly = "[M2] (golly 4.2)\n#R B3/S23\n.*$..*$***$\n$$$$$$$*$\n4 0 0 2 1\n";
+        let glider = Tree::parse(golly).unwrap().cells().unwrap();
+        let expected: HashSet<(i32, i32)> = [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2), (-8, 7)]
+            .into_iter()
+            .collect();
+        assert_eq!(glider, expected);
+        assert!(Tree::parse("[M2]\n4 0 0 0 9\n").is_err());
+        // Each level repeats the one below four times
+        let mut deep = String::from("[M2]\n*$\n");
+        for level in 4..=40 {
+            let c = level - 3;
+            write(
+                &mut deep,
+                format_args!("{} {} {} {} {}\n", level, c, c, c, c),
+            )
+            .unwrap();
+        }
+        let mut tree = Tree::parse(&deep).unwrap();
+        assert_eq!(tree.population(), u64::MAX);
+        assert!(tree.cells().is_err());
+        assert_eq!(tree.cells_in((-8, -8, 16, 16)).len(), 4);
+        // Isolated cells all die, without expanding the tree
+        tree.advance(&Rule::parse("B3/S23").unwrap(), 1);
+        assert_eq!((tree.population(), tree.generation), (0, 1));
+    }
+
+    #[test]
+    fn test_advance() {
+        let rule = Rule::parse("B3/S23").unwrap();
+        // An R-pentomino, a glider and a few scattered cells
+        let mut cells: HashSet<(i32, i32)> = [(1, 0), (2, 0), (0, 1), (1, 1), (1, 2)]
+            .into_iter()
+            .collect();
+        cells.extend([(-40, -30), (-39, -29), (-41, -28), (-40, -28), (-39, -28)]);
+        cells.extend([(70, 3), (71, 3), (72, 3), (-3, 90)]);
+        let mut tree = Tree::from_cells(&cells);
+        let region = (-2000, -2000, 4000, 4000);
+        for n in [1, 5, 37, 64, 300] {
+            for _ in 0..n {
+                rule.step(&mut cells);
+            }
+            tree.advance(&rule, n);
+            assert_eq!(tree.cells_in(region), cells, "after {}", tree.generation);
+            assert_eq!(tree.population(), cells.len() as u64);
+        }
+        assert_eq!(tree.generation, 407);
+        // What has been run is written as it is
+        let back = Tree::parse(&tree.write()).unwrap();
+        assert_eq!(back.cells().unwrap(), cells);
+        assert_eq!(back.generation, 407);
+        // Another rule starts over
+        let mut tree = Tree::from_cells(&cells);
+        let highlife = Rule::parse("B36/S23").unwrap();
+        tree.advance(&highlife, 20);
+        for _ in 0..20 {
+            highlife.step(&mut cells);
+        }
+        assert_eq!(tree.cells_in(region), cells);
+    }
+}
@@ -3,24 +3,29 @@
 
 extern crate cursive;
 
+mod cli;
+mod config;
+mod recorder;
+
 use cursive::align::HAlign;
 use cursive::{
     direction::Direction,
     event::{Event, EventResult, Key, MouseButton, MouseEvent},
     menu,
-    theme::{Color, ColorStyle},
+    theme::{Color, ColorStyle, ColorType},
     traits::*,
     view::CannotFocus,
     view::SizeConstraint,
     views::{
-        Canvas, Dialog, EditView, LinearLayout, OnLayoutView, ProgressBar, ResizedView, SelectView,
-        TextView,
+        Canvas, Checkbox, Dialog, EditView, LinearLayout, OnLayoutView, ProgressBar, ResizedView,
+        SelectView, TextView,
     },
     Cursive, Printer,
 };
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashSet, VecDeque};
 use std::fmt::write;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::RwLock;
@@ -30,8 +35,19 @@ use std::sync::RwLock;
 use db::Result as Dbres;
 //use std::sync::atomic::{AtomicUsize, Ordering};
 
-mod db;
-use db::Storage;
+use db::{RecordMeta, Revision};
+use draw::Tool;
+use history::{History, Sample, Series};
+use life_cursive::{
+    bundle, cast, db, draw, formats, history, macrocell, patterns, render, rule, search, soup,
+    store, symmetry, trace, universe,
+};
+
+use soup::Soup;
+use store::PatternStore;
+use symmetry::{DrawSymmetry, Symmetry};
+use trace::{Tracker, ViewMode};
+use universe::Universe;
 
 fn _get_field_style(cursor: bool) -> ColorStyle {
     if cursor {
@@ -41,78 +57,181 @@ fn _get_field_style(cursor: bool) -> ColorStyle {
     }
 }
 
-fn _update_step(f: &mut HashSet<(i32, i32)>) {
-    let mut nc: HashMap<(i32, i32), i32> = HashMap::new();
-    for (cx, cy) in f.iter() {
-        for dx in -1..2 {
-            for dy in -1..2 {
-                if dx != 0 || dy != 0 {
-                    let (nx, ny) = (cx + dx, cy + dy);
-                    match nc.get_mut(&(nx, ny)) {
-                        Some(q) => {
-                            *q = *q + 1;
-                        }
-                        None => {
-                            nc.insert((nx, ny), 1);
-                        }
-                    }
-                }
+fn _get_selection_style() -> ColorStyle {
+    ColorStyle::new(Color::Rgb(144, 144, 255), Color::Rgb(64, 64, 160))
+}
+
+fn _get_axis_style() -> ColorStyle {
+    ColorStyle::new(Color::Rgb(144, 144, 255), Color::Rgb(48, 48, 208))
+}
+
+/// Text and style of the cell in the view modes other than plain, None to
+/// leave it as drawn in plain mode; `theme` is the one of `_theme`
+fn _get_cell_style(
+    gd: &Gamedata,
+    theme: &render::Style,
+    cell: (i32, i32),
+) -> Option<(&'static str, ColorStyle)> {
+    let rgb = |c: render::Colour| Color::Rgb(c[0], c[1], c[2]);
+    let (alive_fg, dead_bg) = (theme.alive, theme.dead);
+    let alive = gd.universe.cells.contains(&cell);
+    match gd.view_mode {
+        ViewMode::Plain => None,
+        ViewMode::Age if alive => {
+            // Young cells white, going through yellow to red past 64 generations
+            let t = (gd.tracker.age(cell) as f64).ln_1p() / 64f64.ln_1p();
+            let c = trace::gradient(&[[255, 255, 255], [255, 224, 64], [224, 32, 32]], t);
+            Some(("@", ColorStyle::new(rgb(c), rgb(dead_bg))))
+        }
+        ViewMode::Heat => {
+            let heat = gd.tracker.heat(cell);
+            if heat == 0.0 {
+                return None;
             }
+            let c = trace::gradient(
+                &[dead_bg, [160, 32, 160], [240, 64, 32], [255, 224, 64]],
+                heat,
+            );
+            let text = if alive { "@" } else { "." };
+            Some((text, ColorStyle::new(rgb(alive_fg), rgb(c))))
         }
+        ViewMode::Trails if !alive => {
+            let d = gd.tracker.trail(cell)?;
+            let c = trace::gradient(&[alive_fg, dead_bg], d as f64 / (trace::TRAIL + 1) as f64);
+            Some(("o", ColorStyle::new(rgb(c), rgb(dead_bg))))
+        }
+        _ => None,
     }
-    let born: Vec<(i32, i32)> = nc
-        .keys()
-        .filter(|&c| nc[c] == 3 && !f.contains(c))
-        .cloned()
-        .collect();
-    let died: Vec<(i32, i32)> = f
-        .iter()
-        .filter(|&c| {
-            let n = match nc.get(c) {
-                Some(v) => *v,
-                None => 0,
-            };
-            (n < 2 || n > 3) && f.contains(c)
-        })
-        .cloned()
-        .collect();
-    for d in died {
-        f.remove(&d);
+}
+
+/// Image colours of the field view: live cells in the foreground colour,
+/// the grid in the selection background
+fn _theme() -> render::Style {
+    let rgb = |c: ColorType| match c {
+        ColorType::Color(Color::Rgb(r, g, b)) => [r, g, b],
+        _ => [0, 0, 0],
+    };
+    let field = _get_field_style(false);
+    render::Style {
+        alive: rgb(field.front),
+        dead: rgb(field.back),
+        grid: Some(rgb(_get_selection_style().back)),
+        ..Default::default()
     }
-    for b in born {
-        f.insert(b);
+}
+
+/// Metadata of a new position: no description, Life
+fn _new_meta() -> RecordMeta {
+    RecordMeta {
+        rule: rule::LIFE.to_string(),
+        ..RecordMeta::default()
     }
 }
 
 struct Gamedata {
-    storage: Storage,
-    field: HashSet<(i32, i32)>,
-    search: Box<Vec<(i32, i32)>>,
+    storage: Box<dyn PatternStore>,
+    // File of the open archive
+    db_path: PathBuf,
+    // The cells, their generation and the rule evolving them; the rule is
+    // the one of meta, Life when it cannot be parsed
+    universe: Universe,
+    // Pattern too large for the field, run as a quadtree; the field then
+    // holds only its visible part
+    tree: Option<macrocell::Tree>,
+    search: Vec<(i32, i32)>,
     start_x: i32,
     start_y: i32,
+    // Cells shown by the field view, updated when it is drawn
+    view_size: (i32, i32),
     edit_x: i32,
     edit_y: i32,
     edit_mode: bool,
     do_center: bool,
     do_search: bool,
     num_reps: i32,
+    // Corner of the selection, the other one being the edit cursor
+    mark: Option<(i32, i32)>,
+    // The last soup put on the field, saved along with the position
+    soup: Option<Soup>,
+    draw_symmetry: DrawSymmetry,
+    // Doubled coordinates of the symmetry centre
+    sym_centre2: (i32, i32),
+    tool: Tool,
+    // First corner of the shape being drawn
+    anchor: Option<(i32, i32)>,
+    // Last cell painted while dragging, and the value painted
+    last_drag: Option<(i32, i32)>,
+    paint: bool,
+    // Description, author, rule and tags of the current position
+    meta: RecordMeta,
+    // Name of the record last loaded or saved
+    name: String,
+    // Recording of the terminal, written by the backend
+    session: recorder::Handle,
+    frames: Option<FrameLog>,
+    // Population and other measures of the generations run
+    history: History,
+    // How cells are coloured, and what it needs, tracked unless plain
+    view_mode: ViewMode,
+    tracker: Tracker,
+}
+
+/// Text frames of the field, one per generation
+struct FrameLog {
+    path: PathBuf,
+    recorder: cast::FrameRecorder<std::io::BufWriter<std::fs::File>>,
+    // First write error, the recording stops there
+    error: Option<std::io::Error>,
+}
+
+impl FrameLog {
+    fn record(
+        &mut self,
+        generation: i64,
+        cells: &HashSet<(i32, i32)>,
+        region: (i32, i32, i32, i32),
+    ) {
+        if self.error.is_none() {
+            if let Err(e) = self.recorder.frame(generation, cells, region) {
+                self.error = Some(e);
+            }
+        }
+    }
 }
 
 impl Gamedata {
-    pub fn new() -> Gamedata {
-        Gamedata {
-            storage: Storage::new("lf.db"),
-            field: HashSet::new(),
-            search: Box::new(vec![]),
+    pub fn new(db_path: &Path) -> Dbres<Gamedata> {
+        Ok(Gamedata {
+            storage: store::open(db_path)?,
+            db_path: db_path.to_path_buf(),
+            universe: Universe::default(),
+            tree: None,
+            search: vec![],
             start_x: 0,
             start_y: 0,
+            view_size: (0, 0),
             edit_x: 0,
             edit_y: 0,
             edit_mode: true,
             do_center: false,
             do_search: false,
             num_reps: 1000,
-        }
+            mark: None,
+            soup: None,
+            draw_symmetry: DrawSymmetry::Off,
+            sym_centre2: (0, 0),
+            tool: Tool::Pencil,
+            anchor: None,
+            last_drag: None,
+            paint: true,
+            meta: _new_meta(),
+            name: String::new(),
+            session: Rc::new(RefCell::new(None)),
+            frames: None,
+            history: History::default(),
+            view_mode: ViewMode::Plain,
+            tracker: Tracker::default(),
+        })
     }
 
     pub fn toggle_cell(&mut self, x: i32, y: i32) {
@@ -120,24 +239,355 @@ impl Gamedata {
             self.edit_x = x;
             self.edit_y = y;
         }
-        if self.field.contains(&(x, y)) {
-            self.field.remove(&(x, y));
-        } else {
-            self.field.insert((x, y));
+        let alive = !self.universe.cells.contains(&(x, y));
+        self.set_cell(x, y, alive);
+    }
+
+    /// Sets the cell and its images under the drawing symmetry
+    pub fn set_cell(&mut self, x: i32, y: i32, alive: bool) {
+        if self.tree.is_some() {
+            return;
+        }
+        for c in self.draw_symmetry.images(self.sym_centre2, (x, y)) {
+            if alive {
+                self.universe.cells.insert(c);
+            } else {
+                self.universe.cells.remove(&c);
+            }
+        }
+    }
+
+    pub fn set_tool(&mut self, tool: Tool) {
+        self.tool = tool;
+        self.anchor = None;
+    }
+
+    /// Click or <SPACE> with the current tool at the cell. Shapes take two of
+    /// them: the first one sets the anchor, the second one draws.
+    pub fn apply_tool(&mut self, x: i32, y: i32) {
+        self.edit_x = x;
+        self.edit_y = y;
+        match self.tool {
+            Tool::Pencil => {
+                self.toggle_cell(x, y);
+                self.paint = self.universe.cells.contains(&(x, y));
+                self.last_drag = Some((x, y));
+            }
+            Tool::Fill => {
+                if let Some(cells) = draw::flood_fill(&self.universe.cells, (x, y)) {
+                    for (cx, cy) in cells {
+                        self.set_cell(cx, cy, true);
+                    }
+                }
+            }
+            tool => match self.anchor {
+                Some(a) => {
+                    for (cx, cy) in tool.shape(a, (x, y)) {
+                        self.set_cell(cx, cy, true);
+                    }
+                    self.anchor = None;
+                }
+                None => {
+                    self.anchor = Some((x, y));
+                }
+            },
+        }
+    }
+
+    /// Mouse moved to the cell with the left button held
+    pub fn drag_to(&mut self, x: i32, y: i32) {
+        self.edit_x = x;
+        self.edit_y = y;
+        if self.tool == Tool::Pencil {
+            if let Some(last) = self.last_drag {
+                let paint = self.paint;
+                for (cx, cy) in draw::line(last, (x, y)) {
+                    self.set_cell(cx, cy, paint);
+                }
+            }
+            self.last_drag = Some((x, y));
+        }
+    }
+
+    /// Left button released over the cell: finishes a dragged shape
+    pub fn release_at(&mut self, x: i32, y: i32) {
+        self.last_drag = None;
+        if self.tool.is_shape() && self.anchor.is_some() && self.anchor != Some((x, y)) {
+            self.apply_tool(x, y);
+        }
+    }
+
+    /// Puts the pattern with its top left corner at the edit cursor
+    pub fn stamp(&mut self, cells: &[(i32, i32)]) {
+        let (ex, ey) = (self.edit_x, self.edit_y);
+        for (x, y) in cells {
+            self.set_cell(ex + x, ey + y, true);
+        }
+    }
+
+    /// Cells of the shape being drawn, from the anchor to the cursor
+    pub fn shape_preview(&self) -> Vec<(i32, i32)> {
+        match self.anchor {
+            Some(a) if self.tool.is_shape() => self.tool.shape(a, (self.edit_x, self.edit_y)),
+            _ => vec![],
         }
     }
 
-    pub fn save(&mut self, name: &str) -> Dbres<i64> {
-        self.storage.save(name, &self.field)
+    /// Puts the symmetry centre on the cursor cell, or on its lower right
+    /// corner for patterns of even size.
+    pub fn set_sym_centre(&mut self, corner: bool) {
+        let d = if corner { 1 } else { 0 };
+        self.sym_centre2 = (2 * self.edit_x + d, 2 * self.edit_y + d);
     }
 
-    pub fn records(&self) -> Dbres<Vec<(i64, String)>> {
-        self.storage.get_records()
+    /// Switches to edit mode with the cursor in the top left corner of the
+    /// view; the cursor stays where it is if already editing.
+    pub fn enter_edit_mode(&mut self) {
+        if !self.edit_mode && self.tree.is_none() {
+            self.edit_mode = true;
+            self.edit_x = self.start_x;
+            self.edit_y = self.start_y;
+        }
+    }
+
+    pub fn toggle_mark(&mut self) {
+        self.mark = match self.mark {
+            Some(_) => None,
+            None => Some((self.edit_x, self.edit_y)),
+        };
+    }
+
+    /// Selected rectangle as (x, y, width, height)
+    pub fn selection(&self) -> Option<(i32, i32, i32, i32)> {
+        self.mark.map(|(mx, my)| {
+            let (x, y) = (mx.min(self.edit_x), my.min(self.edit_y));
+            (
+                x,
+                y,
+                mx.max(self.edit_x) - x + 1,
+                my.max(self.edit_y) - y + 1,
+            )
+        })
+    }
+
+    /// Live cells of the selected rectangle
+    pub fn selected_cells(&self) -> Vec<(i32, i32)> {
+        match self.selection() {
+            Some((x, y, w, h)) => self
+                .universe
+                .cells
+                .iter()
+                .filter(|(cx, cy)| *cx >= x && *cx < x + w && *cy >= y && *cy < y + h)
+                .cloned()
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn random_fill(&mut self, soup: Soup) {
+        soup.fill(&mut self.universe.cells);
+        self.search.clear();
+        self.soup = Some(soup);
+    }
+
+    fn record_meta(&self) -> RecordMeta {
+        RecordMeta {
+            generation: self.universe.generation,
+            ..self.meta.clone()
+        }
+    }
+
+    pub fn save(&mut self, name: &str) -> Dbres<()> {
+        let meta = self.record_meta();
+        self.storage
+            .save(name, &self.universe.cells, self.soup.as_ref(), &meta)?;
+        self.name = name.to_string();
+        Ok(())
+    }
+
+    /// Saves over the existing record of that name
+    pub fn overwrite(&mut self, name: &str) -> Dbres<()> {
+        let meta = self.record_meta();
+        self.storage
+            .overwrite(name, &self.universe.cells, self.soup.as_ref(), &meta)?;
+        self.name = name.to_string();
+        Ok(())
+    }
+
+    /// Renames the record, which stays the current one if it was
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Dbres<()> {
+        self.storage.rename(name, new_name)?;
+        if self.name == name {
+            self.name = new_name.to_string();
+        }
+        Ok(())
+    }
+
+    pub fn load(&mut self, name: &str) -> Dbres<()> {
+        let cells = self.storage.load(name)?;
+        let soup = self.storage.load_soup(name)?;
+        let meta = self.storage.load_meta(name)?;
+        self.set_record(name, cells, soup, meta);
+        Ok(())
+    }
+
+    /// Loads an older revision of the record into the field; the archive is
+    /// not changed.
+    pub fn load_revision(&mut self, name: &str, rev: &Revision) -> Dbres<()> {
+        let cells = self.storage.load_revision(name, rev.number)?;
+        let (soup, meta) = self.storage.load_revision_meta(name, rev.number)?;
+        self.set_record(name, cells, soup, meta);
+        self.universe.generation = rev.generation;
+        Ok(())
+    }
+
+    /// Makes the record read from the archive the current position. Called
+    /// once everything is read, so that a failed load leaves the field as
+    /// it was.
+    fn set_record(
+        &mut self,
+        name: &str,
+        cells: Vec<(i32, i32)>,
+        soup: Option<Soup>,
+        mut meta: RecordMeta,
+    ) {
+        // Records saved before rules were kept ran Life
+        if meta.rule.is_empty() {
+            meta.rule = rule::LIFE.to_string();
+        }
+        self.universe = Universe {
+            cells: cells.into_iter().collect(),
+            rule: rule::Rule::parse(&meta.rule).unwrap_or(rule::LIFE),
+            generation: meta.generation,
+        };
+        self.tree = None;
+        self.search.clear();
+        self.soup = soup;
+        self.meta = meta;
+        self.name = name.to_string();
+        self.history.clear();
+        self.tracker.clear();
+    }
+
+    /// Makes the macrocell pattern the current position. Patterns too large
+    /// for the field are kept as a quadtree, which can be run and exported
+    /// but not edited.
+    pub fn open_macrocell(&mut self, name: &str, tree: macrocell::Tree) {
+        let meta = RecordMeta {
+            description: tree.description.clone(),
+            rule: tree.rule.clone(),
+            generation: tree.generation,
+            ..RecordMeta::default()
+        };
+        match tree.cells() {
+            Ok(cells) => self.set_record(name, cells.into_iter().collect(), None, meta),
+            Err(_) => {
+                self.set_record(name, vec![], None, meta);
+                self.tree = Some(tree);
+                self.edit_mode = false;
+                self.mark = None;
+                self.anchor = None;
+                (self.edit_x, self.edit_y) = (0, 0);
+                self.do_center = true;
+                self.show_tree_part();
+            }
+        }
+    }
+
+    /// Puts the visible part of the quadtree, if any, into the field
+    pub fn show_tree_part(&mut self) {
+        if let Some(tree) = &self.tree {
+            self.universe.cells = tree.cells_in(self.viewport());
+        }
+    }
+
+    /// Loads the record, runs it up to the generation of the match and moves
+    /// the cursor there.
+    pub fn show_match(&mut self, name: &str, m: &search::Match) -> Dbres<()> {
+        self.load(name)?;
+        for _ in 0..m.generation {
+            self.update();
+        }
+        self.mark = None;
+        self.edit_x = m.x;
+        self.edit_y = m.y;
+        self.do_center = true;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.universe = Universe::default();
+        self.tree = None;
+        self.search.clear();
+        self.soup = None;
+        self.meta = _new_meta();
+        self.name.clear();
+        self.history.clear();
+        self.tracker.clear();
+    }
+
+    /// Switches to another archive, creating it if needed. The field is
+    /// cleared since it no longer belongs to a record of the archive.
+    pub fn open_archive(&mut self, path: &Path) -> Dbres<()> {
+        config::prepare_dir(path)?;
+        self.storage = store::open(path)?;
+        self.db_path = path.to_path_buf();
+        self.clear();
+        Ok(())
+    }
+
+    /// Visible part of the field as (x, y, width, height)
+    pub fn viewport(&self) -> (i32, i32, i32, i32) {
+        (
+            self.start_x,
+            self.start_y,
+            self.view_size.0,
+            self.view_size.1,
+        )
+    }
+
+    pub fn records(&self) -> Dbres<Vec<String>> {
+        self.storage.list()
     }
 
     pub fn update(&mut self) {
         self.search.clear();
-        _update_step(&mut self.field);
+        if let Some(tree) = self.tree.as_mut() {
+            // Neither measured nor tracked, only the visible part is known
+            tree.advance(&self.universe.rule, 1);
+            self.universe.generation = tree.generation;
+            self.show_tree_part();
+            self.record_frame();
+            return;
+        }
+        self.history
+            .begin(self.universe.generation, &self.universe.cells);
+        // Only the tracker needs the previous generation
+        let prev = (self.view_mode != ViewMode::Plain).then(|| self.universe.cells.clone());
+        let counts = self.universe.step_counted();
+        self.history.push(Sample::stepped(
+            self.universe.generation,
+            &self.universe.cells,
+            counts,
+        ));
+        if let Some(prev) = prev {
+            self.tracker.observe(&prev, &self.universe.cells);
+        }
+        self.record_frame();
+    }
+
+    /// Switches to the next view mode, which starts tracking the cells afresh
+    pub fn next_view_mode(&mut self) {
+        self.view_mode = self.view_mode.next();
+        self.tracker.clear();
+    }
+
+    /// Adds the visible part of the field to the frame recording, if any
+    pub fn record_frame(&mut self) {
+        let region = self.viewport();
+        if let Some(log) = self.frames.as_mut() {
+            log.record(self.universe.generation, &self.universe.cells, region);
+        }
     }
 }
 
@@ -188,7 +638,25 @@ impl cursive::view::View for FieldView {
                 }
                 Event::Char(' ') => {
                     let (x, y) = (gdata.edit_x, gdata.edit_y);
-                    gdata.toggle_cell(x, y);
+                    gdata.apply_tool(x, y);
+                }
+                Event::Char(c) if Tool::from_key(c).is_some() => {
+                    gdata.set_tool(Tool::from_key(c).unwrap());
+                }
+                Event::Char('m') => {
+                    gdata.toggle_mark();
+                }
+                Event::Char('s') => {
+                    gdata.draw_symmetry = gdata.draw_symmetry.next();
+                }
+                Event::Char('c') => {
+                    gdata.set_sym_centre(false);
+                }
+                Event::Char('C') => {
+                    gdata.set_sym_centre(true);
+                }
+                Event::Char('v') => {
+                    gdata.next_view_mode();
                 }
                 Event::Mouse {
                     offset,
@@ -204,11 +672,32 @@ impl cursive::view::View for FieldView {
                             gdata.do_center = true;
                         }
                         MouseButton::Left => {
-                            gdata.toggle_cell(x, y);
+                            gdata.apply_tool(x, y);
                         }
                         _ => (),
                     };
                 }
+                Event::Mouse {
+                    offset,
+                    position,
+                    event: MouseEvent::Hold(MouseButton::Left),
+                } => {
+                    // Dragging can leave the view
+                    let pos = position.saturating_sub(offset);
+                    let x = (pos.x as i32) / 2 + gdata.start_x;
+                    let y = (pos.y as i32) + gdata.start_y;
+                    gdata.drag_to(x, y);
+                }
+                Event::Mouse {
+                    offset,
+                    position,
+                    event: MouseEvent::Release(MouseButton::Left),
+                } => {
+                    let pos = position.saturating_sub(offset);
+                    let x = (pos.x as i32) / 2 + gdata.start_x;
+                    let y = (pos.y as i32) + gdata.start_y;
+                    gdata.release_at(x, y);
+                }
                 _ => (),
             }
         } else {
@@ -230,9 +719,7 @@ impl cursive::view::View for FieldView {
                             gdata.start_y += 1;
                         }
                         Key::F4 => {
-                            gdata.edit_mode = true;
-                            gdata.edit_x = gdata.start_x;
-                            gdata.edit_y = gdata.start_y;
+                            gdata.enter_edit_mode();
                         }
                         Key::F5 => {
                             gdata.do_search = true;
@@ -243,6 +730,9 @@ impl cursive::view::View for FieldView {
                 Event::Char(' ') => {
                     gdata.update();
                 }
+                Event::Char('v') => {
+                    gdata.next_view_mode();
+                }
                 Event::Mouse {
                     offset,
                     position,
@@ -275,15 +765,23 @@ impl cursive::view::View for FieldView {
         let y_max = p.size.y as i32;
         let style = _get_field_style(false);
         let cursor_style = _get_field_style(true);
+        let selection_style = _get_selection_style();
+        let axis_style = _get_axis_style();
 
-        let x_f = ((x_max + 1) / 2) as i32;
+        let x_f = (x_max + 1) / 2;
+        gdata.view_size = (x_f, y_max);
 
         let visible =
             |x: i32, y: i32, sx: i32, sy: i32| x >= sx && y >= sy && x < sx + x_f && y < sy + y_max;
 
         if gdata.do_search {
             if gdata.search.is_empty() {
-                gdata.search = Box::new(gdata.field.iter().cloned().collect::<Vec<(i32, i32)>>());
+                gdata.search = gdata
+                    .universe
+                    .cells
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<(i32, i32)>>();
             }
             if !gdata.search.is_empty() {
                 let (x, y) = gdata.search[0];
@@ -291,17 +789,15 @@ impl cursive::view::View for FieldView {
                 gdata.edit_y = y;
                 gdata.start_x = gdata.edit_x - x_f / 2;
                 gdata.start_y = gdata.edit_y - y_max / 2;
-                gdata.search = Box::new(
-                    gdata
-                        .search
-                        .iter()
-                        .filter(|&p| {
-                            let (px, py) = *p;
-                            !visible(px, py, gdata.start_x, gdata.start_y)
-                        })
-                        .cloned()
-                        .collect::<Vec<(i32, i32)>>(),
-                );
+                gdata.search = gdata
+                    .search
+                    .iter()
+                    .filter(|&p| {
+                        let (px, py) = *p;
+                        !visible(px, py, gdata.start_x, gdata.start_y)
+                    })
+                    .cloned()
+                    .collect::<Vec<(i32, i32)>>();
             }
         }
         gdata.do_search = false;
@@ -326,10 +822,13 @@ impl cursive::view::View for FieldView {
             }
         }
 
+        gdata.show_tree_part();
+        let preview: HashSet<(i32, i32)> = gdata.shape_preview().into_iter().collect();
+        let theme = _theme();
         for y in gdata.start_y..y_max + gdata.start_y {
             let mut s = String::new();
             for x in gdata.start_x..x_f + gdata.start_x {
-                if gdata.field.contains(&(x, y)) {
+                if gdata.universe.cells.contains(&(x, y)) {
                     write(&mut s, format_args!("@ ")).unwrap();
                 } else {
                     write(&mut s, format_args!(". ")).unwrap();
@@ -338,14 +837,65 @@ impl cursive::view::View for FieldView {
             p.with_color(style, |printer| {
                 printer.print((0, y - gdata.start_y), &s);
             });
+            if gdata.view_mode != ViewMode::Plain {
+                for x in gdata.start_x..x_f + gdata.start_x {
+                    if let Some((text, cell_style)) = _get_cell_style(&gdata, &theme, (x, y)) {
+                        p.with_color(cell_style, |printer| {
+                            printer.print(((x - gdata.start_x) * 2, y - gdata.start_y), text)
+                        });
+                    }
+                }
+            }
+            if gdata.edit_mode && gdata.draw_symmetry != DrawSymmetry::Off {
+                for x in gdata.start_x..x_f + gdata.start_x {
+                    if gdata.draw_symmetry.on_axis(gdata.sym_centre2, (x, y)) {
+                        p.with_color(axis_style, |printer| {
+                            printer.print(
+                                ((x - gdata.start_x) * 2, y - gdata.start_y),
+                                if gdata.universe.cells.contains(&(x, y)) {
+                                    "@ "
+                                } else {
+                                    ". "
+                                },
+                            )
+                        });
+                    }
+                }
+            }
+            if let Some((sx, sy, sw, sh)) = gdata.selection() {
+                if y >= sy && y < sy + sh {
+                    for x in sx.max(gdata.start_x)..(sx + sw).min(gdata.start_x + x_f) {
+                        p.with_color(selection_style, |printer| {
+                            printer.print(
+                                ((x - gdata.start_x) * 2, y - gdata.start_y),
+                                if gdata.universe.cells.contains(&(x, y)) {
+                                    "@"
+                                } else {
+                                    "."
+                                },
+                            )
+                        });
+                    }
+                }
+            }
+            // Shape being drawn, cells it would set shown alive
+            if gdata.edit_mode && !preview.is_empty() {
+                for x in gdata.start_x..x_f + gdata.start_x {
+                    if preview.contains(&(x, y)) {
+                        p.with_color(selection_style, |printer| {
+                            printer.print(((x - gdata.start_x) * 2, y - gdata.start_y), "@")
+                        });
+                    }
+                }
+            }
             // Drawing cursor if it is in the current line (edit mode only)
             if gdata.edit_mode && y == gdata.edit_y {
                 let cpos = (gdata.edit_x - gdata.start_x) * 2;
-                if cpos >= 0 && cpos <= x_max as i32 {
+                if cpos >= 0 && cpos <= x_max {
                     p.with_color(cursor_style, |printer| {
                         printer.print(
                             (cpos, y - gdata.start_y),
-                            if gdata.field.contains(&(gdata.edit_x, y as i32)) {
+                            if gdata.universe.cells.contains(&(gdata.edit_x, y)) {
                                 "@"
                             } else {
                                 "."
@@ -366,7 +916,7 @@ fn _leave_dialog(siv: &mut Cursive) {
     siv.set_autohide_menu(false);
     siv.clear_global_callbacks(Key::Esc);
     siv.add_global_callback(Key::Esc, |s| s.select_menubar());
-    siv.add_global_callback(Key::F1, |s| _help(s));
+    siv.add_global_callback(Key::F1, _help);
 }
 
 fn _enter_dialog(siv: &mut Cursive) {
@@ -379,15 +929,30 @@ fn _enter_dialog(siv: &mut Cursive) {
     siv.clear_global_callbacks(Key::F1);
 }
 
+/// Reports a failed storage operation
+fn _error(siv: &mut Cursive, e: db::Error) {
+    siv.add_layer(Dialog::info(e.to_string()).title("Error"));
+}
+
 const HELP_TEXT: &str = "ALL MODES:
   <F1> displays this help
   <F4> toggles between the edit and playback modes
   <F5> to search for live cells (cycles through the visible parts)
+  <v> cycles the view: plain, cell age, heat map of the changes over the
+      last 32 generations, trails of the dead cells
   Right-Click to center
 
 EDIT MODE:
   Arrows to position the cursor for keyboard editing
   Left-click or space to toggle cell
+  <m> to set or clear the selection mark (selects up to the cursor)
+  <s> cycles the drawing symmetry (off, mirror X/Y, 2-, 4-, 8-fold)
+  <c> centres the symmetry on the cursor, <C> on its lower right corner
+  Drawing tools (also in the Draw menu):
+    <p> pencil: click or space toggles, drag with left button paints
+    <l> line, <r>/<R> rectangle/filled, <e>/<E> ellipse/filled:
+        drag with left button, or click/space on both corners
+    <f> flood fill of the enclosed dead region
   
 PLAYBACK MODE:
   <SPACE> to step forward
@@ -410,22 +975,55 @@ fn _help(siv: &mut Cursive) {
 }
 
 fn _exec_task(siv: &mut Cursive, num_reps: i32) {
-    let f: Arc<RwLock<HashSet<(i32, i32)>>>;
+    let tree_mode = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        gd.tree.is_some()
+    };
+    if tree_mode {
+        _exec_tree_task(siv, num_reps);
+        return;
+    }
+    let u: Arc<RwLock<Universe>>;
+    let mut tracker: Option<Tracker> = None;
+    // Also stepped along, every generation recorded in the visible part
+    let mut frames: Option<FrameLog>;
+    let region: (i32, i32, i32, i32);
     {
         let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
         gd.num_reps = num_reps;
-        f = Arc::new(RwLock::new(gd.field.clone()));
+        u = Arc::new(RwLock::new(gd.universe.clone()));
+        let gd = &mut *gd;
+        gd.history.begin(gd.universe.generation, &gd.universe.cells);
+        // Stepped along with the field, given back at the end
+        if gd.view_mode != ViewMode::Plain {
+            tracker = Some(std::mem::take(&mut gd.tracker));
+        }
+        frames = gd.frames.take();
+        region = gd.viewport();
     }
     let cb = siv.cb_sink().clone();
-    let f1 = Arc::clone(&f);
+    let u1 = Arc::clone(&u);
     siv.add_layer(Dialog::around(
         ProgressBar::new()
             .range(0, num_reps as usize / 100)
             .with_task(move |counter| {
+                // Only the last ones would be kept by the history
+                let mut samples = VecDeque::new();
                 for c in 0..num_reps {
                     {
-                        let mut fg = f1.write().unwrap();
-                        _update_step(&mut *fg);
+                        let mut ug = u1.write().unwrap();
+                        let prev = tracker.as_ref().map(|_| ug.cells.clone());
+                        let counts = ug.step_counted();
+                        if samples.len() == history::MAX_SAMPLES {
+                            samples.pop_front();
+                        }
+                        samples.push_back(Sample::stepped(ug.generation, &ug.cells, counts));
+                        if let (Some(t), Some(prev)) = (tracker.as_mut(), prev) {
+                            t.observe(&prev, &ug.cells);
+                        }
+                        if let Some(log) = frames.as_mut() {
+                            log.record(ug.generation, &ug.cells, region);
+                        }
                     }
                     if c % 100 == 0 {
                         counter.tick(1);
@@ -435,13 +1033,52 @@ fn _exec_task(siv: &mut Cursive, num_reps: i32) {
                     s.pop_layer();
                     _leave_dialog(s);
                     let mut gd = (*s.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
-                    gd.field.clear();
-                    {
-                        let fg = f.read().unwrap();
-                        for xy in (*fg).iter() {
-                            gd.field.insert(*xy);
-                        }
+                    gd.universe = std::mem::take(&mut *u.write().unwrap());
+                    gd.search.clear();
+                    for sample in samples {
+                        gd.history.push(sample);
+                    }
+                    if let Some(t) = tracker {
+                        gd.tracker = t;
+                    }
+                    gd.frames = frames;
+                }))
+                .unwrap();
+            })
+            .full_width(),
+    ));
+    siv.set_autorefresh(true);
+}
+
+/// Fast forward of a quadtree, a power of two generations at a time. Only
+/// the last generation is recorded.
+fn _exec_tree_task(siv: &mut Cursive, num_reps: i32) {
+    let (mut tree, rule) = {
+        let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+        gd.num_reps = num_reps;
+        (gd.tree.take().unwrap(), gd.universe.rule)
+    };
+    let generations = num_reps.max(0) as u64;
+    let cb = siv.cb_sink().clone();
+    siv.add_layer(Dialog::around(
+        ProgressBar::new()
+            .range(0, num_reps as usize / 100)
+            .with_task(move |counter| {
+                for j in 0..64 {
+                    if generations >> j & 1 != 0 {
+                        tree.advance(&rule, 1 << j);
+                        counter.tick((1 << j) / 100);
                     }
+                }
+                cb.send(Box::new(move |s: &mut Cursive| {
+                    s.pop_layer();
+                    _leave_dialog(s);
+                    let mut gd = (*s.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                    gd.universe.generation = tree.generation;
+                    gd.tree = Some(tree);
+                    gd.search.clear();
+                    gd.show_tree_part();
+                    gd.record_frame();
                 }))
                 .unwrap();
             })
@@ -458,14 +1095,12 @@ fn _run_multiple_steps(siv: &mut Cursive) {
             .borrow_mut()
             .num_reps;
     }
-    let mut editview =
-        EditView::new().on_submit(|s: &mut Cursive, v: &str| match v.parse::<i32>() {
-            Ok(i) => {
-                s.pop_layer();
-                _exec_task(s, i);
-            }
-            _ => {}
-        });
+    let mut editview = EditView::new().on_submit(|s: &mut Cursive, v: &str| {
+        if let Ok(i) = v.parse::<i32>() {
+            s.pop_layer();
+            _exec_task(s, i);
+        }
+    });
     editview.set_content(num_reps.to_string());
     let dlg = Dialog::new()
         .title("Number of repetitions?")
@@ -477,115 +1112,1784 @@ fn _run_multiple_steps(siv: &mut Cursive) {
     siv.add_layer(dlg);
 }
 
-fn _load(siv: &mut Cursive) {
-    let mut select: SelectView = SelectView::new()
-        // Center the text horizontally
-        .h_align(HAlign::Center)
-        // Use keyboard to jump to the pressed letters
-        .autojump();
-    {
-        let gd = siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap();
-
-        let recs = (*gd).borrow().records().unwrap().clone();
-        for (_, n) in recs {
-            select.add_item_str(n);
-        }
-    }
-    select.set_on_submit(|siv, name: &str| {
-        {
-            let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
-            match gd.storage.load(name) {
-                Ok(v) => {
-                    gd.field.clear();
-                    for (x, y) in v {
-                        gd.field.insert((x, y));
-                    }
-                }
-                Err(_) => {}
-            }
-        }
-        siv.pop_layer();
-        _leave_dialog(siv);
-    });
-
-    siv.add_layer(
-        Dialog::around(select.scrollable())
-            .title("Select a position to load")
-            .button("Cancel", |s| {
-                s.pop_layer();
-                _leave_dialog(s);
-            }),
-    );
-    _enter_dialog(siv);
+fn _labeled_edit(label: &str, name: &str, value: String) -> LinearLayout {
+    LinearLayout::horizontal()
+        .child(TextView::new(label).fixed_width(14))
+        .child(EditView::new().content(value).with_name(name).min_width(22))
 }
 
-fn _delete(siv: &mut Cursive) {
-    let mut select: SelectView = SelectView::new()
-        // Center the text horizontally
-        .h_align(HAlign::Center)
-        // Use keyboard to jump to the pressed letters
-        .autojump();
-    {
-        let gd = siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap();
+fn _get_edit(siv: &mut Cursive, name: &str) -> Rc<String> {
+    siv.call_on_name(name, |view: &mut EditView| view.get_content())
+        .unwrap()
+}
 
-        let recs = (*gd).borrow().records().unwrap().clone();
-        for (_, n) in recs {
-            select.add_item_str(n);
-        }
+fn _read_soup(siv: &mut Cursive) -> Result<Soup, String> {
+    let not_number = || String::from("X, Y, width, height, density and seed must be numbers");
+    let mut nums: Vec<i32> = vec![];
+    for name in ["soup_x", "soup_y", "soup_w", "soup_h"] {
+        nums.push(
+            _get_edit(siv, name)
+                .trim()
+                .parse::<i32>()
+                .map_err(|_| not_number())?,
+        );
     }
-    select.set_on_submit(|siv, name: &str| {
-        {
-            let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
-            gd.storage.delete(name).unwrap();
-        }
-        siv.pop_layer();
-        _leave_dialog(siv);
-    });
-
-    siv.add_layer(
-        Dialog::around(select.scrollable())
-            .title("Select a position to DELETE")
-            .button("Cancel", |s| {
-                s.pop_layer();
-                _leave_dialog(s);
-            }),
-    );
-    _enter_dialog(siv);
+    let density = _get_edit(siv, "soup_density")
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| not_number())?;
+    let seed = _get_edit(siv, "soup_seed")
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| not_number())?;
+    let symmetry = siv
+        .call_on_name("soup_symmetry", |view: &mut SelectView<Symmetry>| {
+            view.selection()
+        })
+        .unwrap()
+        .ok_or_else(|| String::from("No symmetry selected"))?;
+    let soup = Soup {
+        x: nums[0],
+        y: nums[1],
+        width: nums[2],
+        height: nums[3],
+        density: density / 100.0,
+        symmetry: *symmetry,
+        seed,
+    };
+    soup.check()?;
+    Ok(soup)
 }
 
-fn _save(siv: &mut Cursive) {
+fn _random_fill(siv: &mut Cursive) {
+    if _refuse_tree(siv, "Random fill") {
+        return;
+    }
+    let (x, y, w, h);
+    let (density, symmetry);
+    {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        // Default to the selection, or a square around the cursor
+        (x, y, w, h) = gd
+            .selection()
+            .unwrap_or((gd.edit_x - 8, gd.edit_y - 8, 16, 16));
+        (density, symmetry) = match &gd.soup {
+            Some(sp) => (sp.density * 100.0, sp.symmetry),
+            None => (50.0, Symmetry::C1),
+        };
+    }
+    let mut sym_select: SelectView<Symmetry> = SelectView::new().popup();
+    for sym in symmetry::ALL {
+        sym_select.add_item(sym.name(), sym);
+    }
+    sym_select.set_selection(symmetry::ALL.iter().position(|&s| s == symmetry).unwrap());
     let dlg = Dialog::new()
-        .title("Find")
+        .title("Random fill")
         .content(
-            EditView::new()
-                //.on_submit(find)
-                .with_name("save_name")
-                .min_width(10),
+            LinearLayout::vertical()
+                .child(_labeled_edit("X", "soup_x", x.to_string()))
+                .child(_labeled_edit("Y", "soup_y", y.to_string()))
+                .child(_labeled_edit("Width", "soup_w", w.to_string()))
+                .child(_labeled_edit("Height", "soup_h", h.to_string()))
+                .child(_labeled_edit(
+                    "Density, %",
+                    "soup_density",
+                    density.to_string(),
+                ))
+                .child(
+                    LinearLayout::horizontal()
+                        .child(TextView::new("Symmetry").fixed_width(14))
+                        .child(sym_select.with_name("soup_symmetry")),
+                )
+                .child(_labeled_edit(
+                    "Seed",
+                    "soup_seed",
+                    soup::time_seed().to_string(),
+                )),
         )
-        .button("Ok", |siv| {
-            let sv_res: Dbres<i64>;
-            let text = siv
-                .call_on_name("save_name", |view: &mut EditView| view.get_content())
-                .unwrap();
-            {
-                let mut gd = siv
-                    .user_data::<Rc<RefCell<Gamedata>>>()
-                    .unwrap()
-                    .borrow_mut();
-                sv_res = gd.save(&text);
+        .button("Ok", |siv| match _read_soup(siv) {
+            Ok(sp) => {
+                {
+                    let mut gd = siv
+                        .user_data::<Rc<RefCell<Gamedata>>>()
+                        .unwrap()
+                        .borrow_mut();
+                    gd.random_fill(sp);
+                }
+                siv.pop_layer();
+                _leave_dialog(siv);
             }
-            match sv_res {
-                Ok(_) => {
-                    siv.pop_layer();
+            Err(e) => {
+                siv.add_layer(
+                    Dialog::around(TextView::new(e))
+                        .title("Invalid parameters")
+                        .dismiss_button("Ok"),
+                );
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+fn _stamp_selection(siv: &mut Cursive) -> Option<Vec<(i32, i32)>> {
+    let name = siv
+        .call_on_name("stamp_list", |view: &mut SelectView| view.selection())
+        .unwrap()?;
+    let orientation = siv
+        .call_on_name("stamp_orientation", |view: &mut SelectView<usize>| {
+            view.selection()
+        })
+        .unwrap()?;
+    Some(patterns::orient(&patterns::get(&name)?, *orientation))
+}
+
+fn _update_stamp_preview(siv: &mut Cursive) {
+    if let Some(cells) = _stamp_selection(siv) {
+        siv.call_on_name("stamp_preview", |view: &mut TextView| {
+            view.set_content(patterns::preview(&cells, 40, 20))
+        });
+    }
+}
+
+fn _stamp(siv: &mut Cursive) {
+    if _refuse_tree(siv, "Insert a pattern") {
+        return;
+    }
+    let mut select: SelectView = SelectView::new().autojump();
+    for (name, _) in patterns::LIBRARY {
+        select.add_item_str(*name);
+    }
+    select.set_on_select(|siv, _: &String| _update_stamp_preview(siv));
+    let mut orientation: SelectView<usize> = SelectView::new().popup();
+    for (i, name) in patterns::ORIENTATIONS.iter().enumerate() {
+        orientation.add_item(*name, i);
+    }
+    orientation.set_on_submit(|siv, _: &usize| _update_stamp_preview(siv));
+
+    let dlg = Dialog::new()
+        .title("Insert a pattern at the cursor")
+        .content(
+            LinearLayout::horizontal()
+                .child(
+                    LinearLayout::vertical()
+                        .child(select.with_name("stamp_list").scrollable().min_height(10))
+                        .child(orientation.with_name("stamp_orientation")),
+                )
+                .child(
+                    TextView::new("")
+                        .with_name("stamp_preview")
+                        .min_width(42)
+                        .min_height(22),
+                ),
+        )
+        .button("Insert", |siv| {
+            if let Some(cells) = _stamp_selection(siv) {
+                let mut gd = siv
+                    .user_data::<Rc<RefCell<Gamedata>>>()
+                    .unwrap()
+                    .borrow_mut();
+                gd.stamp(&cells);
+            }
+            siv.pop_layer();
+            _leave_dialog(siv);
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+    _update_stamp_preview(siv);
+}
+
+fn _meta_text(meta: &RecordMeta) -> String {
+    let mut s = String::new();
+    let (bx, by, bw, bh) = meta.bbox;
+    write(
+        &mut s,
+        format_args!(
+            "{}\n\nAuthor: {}\nTags: {}\nRule: {}\nGeneration: {}\nPopulation: {}\n\
+             Bounding box: {}x{} at ({},{})\n",
+            meta.description,
+            meta.author,
+            meta.tags.join(", "),
+            meta.rule,
+            meta.generation,
+            meta.population,
+            bw,
+            bh,
+            bx,
+            by
+        ),
+    )
+    .unwrap();
+    if meta.created > 0 {
+        write(
+            &mut s,
+            format_args!(
+                "Created: {}\nModified: {}\n",
+                db::format_timestamp(meta.created),
+                db::format_timestamp(meta.modified)
+            ),
+        )
+        .unwrap();
+    }
+    s
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RecordOrder {
+    Name,
+    Date,
+    Population,
+}
+
+/// Names of the records matching the filter, in the given order. The filter
+/// matches a substring of the name, description or tags; "#tag" only
+/// matches tags.
+fn _filter_records(
+    records: &[(String, RecordMeta)],
+    filter: &str,
+    order: RecordOrder,
+) -> Vec<String> {
+    let filter = filter.trim().to_lowercase();
+    let mut res: Vec<&(String, RecordMeta)> = records
+        .iter()
+        .filter(|(name, meta)| match filter.strip_prefix('#') {
+            Some(tag) => meta.tags.iter().any(|t| t.to_lowercase() == tag),
+            None => {
+                name.to_lowercase().contains(&filter)
+                    || meta.description.to_lowercase().contains(&filter)
+                    || meta.tags.iter().any(|t| t.to_lowercase().contains(&filter))
+            }
+        })
+        .collect();
+    match order {
+        RecordOrder::Name => res.sort_by(|a, b| a.0.cmp(&b.0)),
+        RecordOrder::Date => res.sort_by_key(|r| std::cmp::Reverse(r.1.modified)),
+        RecordOrder::Population => res.sort_by_key(|r| std::cmp::Reverse(r.1.population)),
+    }
+    res.into_iter().map(|(name, _)| name.clone()).collect()
+}
+
+fn _refresh_load_list(siv: &mut Cursive, records: &[(String, RecordMeta)]) {
+    let filter = _get_edit(siv, "load_filter");
+    let order = siv
+        .call_on_name("load_order", |view: &mut SelectView<RecordOrder>| {
+            view.selection()
+        })
+        .unwrap()
+        .map(|o| *o)
+        .unwrap_or(RecordOrder::Name);
+    let names = _filter_records(records, &filter, order);
+    let cb = siv
+        .call_on_name("load_list", |view: &mut SelectView| {
+            view.clear();
+            view.add_all_str(names);
+            view.set_selection(0)
+        })
+        .unwrap();
+    cb(siv);
+}
+
+fn _update_load_preview(siv: &mut Cursive, name: &str) {
+    let text = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        match (gd.storage.load(name), gd.storage.load_meta(name)) {
+            (Ok(cells), Ok(meta)) => {
+                format!(
+                    "{}\n{}",
+                    patterns::preview(&cells, 40, 16),
+                    _meta_text(&meta)
+                )
+            }
+            (Err(e), _) | (_, Err(e)) => e.to_string(),
+        }
+    };
+    siv.call_on_name("load_info", |view: &mut TextView| view.set_content(text));
+}
+
+fn _revisions(siv: &mut Cursive, name: String) {
+    let revs = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        gd.storage.revisions(&name)
+    };
+    let revs = match revs {
+        Ok(revs) => revs,
+        Err(e) => return _error(siv, e),
+    };
+    let mut select: SelectView<Revision> = SelectView::new();
+    let mut last_pop = 0;
+    // Newest first
+    let mut items: Vec<(String, Revision)> = vec![];
+    for rev in revs {
+        let label = format!(
+            "#{:<3} {}  pop {} ({:+}), gen {}",
+            rev.number,
+            db::format_timestamp(rev.created),
+            rev.population,
+            rev.population - last_pop,
+            rev.generation
+        );
+        last_pop = rev.population;
+        items.push((label, rev));
+    }
+    items.reverse();
+    select.add_all(items);
+    let n = name.clone();
+    select.set_on_select(move |siv, rev: &Revision| {
+        let text = {
+            let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+            match gd.storage.load_revision(&n, rev.number) {
+                Ok(cells) => patterns::preview(&cells, 40, 16),
+                Err(e) => e.to_string(),
+            }
+        };
+        siv.call_on_name("revision_preview", |view: &mut TextView| {
+            view.set_content(text)
+        });
+    });
+    let (n_load, n_restore) = (name.clone(), name.clone());
+    siv.add_layer(
+        Dialog::around(
+            LinearLayout::horizontal()
+                .child(
+                    select
+                        .with_name("revision_list")
+                        .scrollable()
+                        .min_width(44)
+                        .min_height(16),
+                )
+                .child(
+                    TextView::new("")
+                        .with_name("revision_preview")
+                        .min_width(42),
+                ),
+        )
+        .title(format!("Revisions of '{}'", name))
+        .button("Load", move |s| {
+            let rev = s
+                .call_on_name("revision_list", |view: &mut SelectView<Revision>| {
+                    view.selection()
+                })
+                .unwrap();
+            if let Some(rev) = rev {
+                let res = {
+                    let mut gd = (*s.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                    gd.load_revision(&n_load, &rev)
+                };
+                if let Err(e) = res {
+                    return _error(s, e);
+                }
+            }
+            // Revisions and the load dialog
+            s.pop_layer();
+            s.pop_layer();
+            _leave_dialog(s);
+        })
+        .button("Restore", move |s| {
+            let rev = s
+                .call_on_name("revision_list", |view: &mut SelectView<Revision>| {
+                    view.selection()
+                })
+                .unwrap();
+            if let Some(rev) = rev {
+                let res = {
+                    let mut gd = (*s.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                    gd.storage
+                        .restore_revision(&n_restore, rev.number)
+                        .and_then(|_| gd.load(&n_restore))
+                };
+                if let Err(e) = res {
+                    return _error(s, e);
+                }
+            }
+            s.pop_layer();
+            s.pop_layer();
+            _leave_dialog(s);
+        })
+        .dismiss_button("Close"),
+    );
+    let cb = siv
+        .call_on_name("revision_list", |view: &mut SelectView<Revision>| {
+            view.set_selection(0)
+        })
+        .unwrap();
+    cb(siv);
+}
+
+/// Loads the record and closes the load dialog, or reports the failure
+fn _load_record(siv: &mut Cursive, name: &str) {
+    let res = {
+        let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+        gd.load(name)
+    };
+    match res {
+        Ok(_) => {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        }
+        Err(e) => _error(siv, e),
+    }
+}
+
+fn _load(siv: &mut Cursive) {
+    let (records, has_revisions) = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        (gd.storage.records_meta(), gd.storage.has_revisions())
+    };
+    let records: Rc<Vec<(String, RecordMeta)>> = match records {
+        Ok(recs) => Rc::new(recs),
+        Err(e) => return _error(siv, e),
+    };
+    let mut select: SelectView = SelectView::new()
+        // Use keyboard to jump to the pressed letters
+        .autojump();
+    let names = _filter_records(&records, "", RecordOrder::Name);
+    select.add_all_str(names.iter().cloned());
+    select.set_on_submit(_load_record);
+    select.set_on_select(|siv, name: &String| _update_load_preview(siv, name));
+
+    let recs = Rc::clone(&records);
+    let filter = EditView::new().on_edit(move |siv, _, _| _refresh_load_list(siv, &recs));
+    let mut order: SelectView<RecordOrder> = SelectView::new().popup();
+    order.add_item("Sort by name", RecordOrder::Name);
+    order.add_item("Newest first", RecordOrder::Date);
+    order.add_item("Largest first", RecordOrder::Population);
+    let recs = Rc::clone(&records);
+    order.set_on_submit(move |siv, _: &RecordOrder| _refresh_load_list(siv, &recs));
+
+    let mut dlg = Dialog::around(
+        LinearLayout::vertical()
+            .child(
+                LinearLayout::horizontal()
+                    .child(TextView::new("Filter "))
+                    .child(filter.with_name("load_filter").min_width(30))
+                    .child(TextView::new(" "))
+                    .child(order.with_name("load_order")),
+            )
+            .child(
+                LinearLayout::horizontal()
+                    .child(
+                        select
+                            .with_name("load_list")
+                            .scrollable()
+                            .min_width(24)
+                            .min_height(20),
+                    )
+                    .child(TextView::new("").with_name("load_info").min_width(42)),
+            ),
+    )
+    .title("Select a position to load");
+    // Only the SQLite archive keeps revisions
+    if has_revisions {
+        dlg.add_button("Revisions", |s| {
+            let name = s
+                .call_on_name("load_list", |view: &mut SelectView| view.selection())
+                .unwrap();
+            if let Some(name) = name {
+                _revisions(s, name.to_string());
+            }
+        });
+    }
+    siv.add_layer(
+        dlg.button("Load", |s| {
+            let name = s
+                .call_on_name("load_list", |view: &mut SelectView| view.selection())
+                .unwrap();
+            match name {
+                Some(name) => _load_record(s, &name),
+                None => {
+                    s.pop_layer();
+                    _leave_dialog(s);
+                }
+            }
+        })
+        .button("Cancel", |s| {
+            s.pop_layer();
+            _leave_dialog(s);
+        }),
+    );
+    _enter_dialog(siv);
+    if let Some(name) = names.first() {
+        _update_load_preview(siv, name);
+    }
+}
+
+/// Names of the saved records to pick one from
+fn _record_select(siv: &mut Cursive) -> Dbres<SelectView> {
+    let recs = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        gd.records()?
+    };
+    let mut select: SelectView = SelectView::new()
+        // Center the text horizontally
+        .h_align(HAlign::Center)
+        // Use keyboard to jump to the pressed letters
+        .autojump();
+    for n in recs {
+        select.add_item_str(n);
+    }
+    Ok(select)
+}
+
+fn _delete(siv: &mut Cursive) {
+    let mut select = match _record_select(siv) {
+        Ok(select) => select,
+        Err(e) => return _error(siv, e),
+    };
+    select.set_on_submit(|siv, name: &str| {
+        let res = {
+            let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+            gd.storage.delete(name)
+        };
+        match res {
+            Ok(_) => {
+                siv.pop_layer();
+                _leave_dialog(siv);
+            }
+            Err(e) => _error(siv, e),
+        }
+    });
+
+    siv.add_layer(
+        Dialog::around(select.scrollable())
+            .title("Select a position to DELETE")
+            .button("Cancel", |s| {
+                s.pop_layer();
+                _leave_dialog(s);
+            }),
+    );
+    _enter_dialog(siv);
+}
+
+/// Archives (.db files) in the directory
+fn _archives_in(dir: &Path) -> Vec<String> {
+    let mut res: Vec<String> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().is_some_and(|x| x == "db"))
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    res.sort();
+    res
+}
+
+/// Opens an existing archive or, with `create`, a new one
+fn _archive(siv: &mut Cursive, create: bool) {
+    let (current, dir) = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        let dir = gd
+            .db_path
+            .parent()
+            .map(|d| d.to_path_buf())
+            .unwrap_or_default();
+        (gd.db_path.clone(), dir)
+    };
+    let path = if create {
+        dir.join("new.db")
+    } else {
+        current.clone()
+    };
+    let mut dlg = Dialog::new()
+        .title(if create {
+            "New archive"
+        } else {
+            "Open archive"
+        })
+        .button(if create { "Create" } else { "Open" }, move |siv| {
+            let mut path = PathBuf::from(_get_edit(siv, "archive_path").trim());
+            // A directory of RLE files rather than a database
+            let rle_dir = create
+                && *siv
+                    .call_on_name("archive_kind", |view: &mut SelectView<bool>| {
+                        view.selection()
+                    })
+                    .unwrap()
+                    .unwrap();
+            if create && !rle_dir && path.extension().is_none() {
+                path.set_extension("db");
+            }
+            let err = if path.as_os_str().is_empty() {
+                Some(String::from("Enter the path of the archive"))
+            } else if create && path.exists() {
+                Some(format!("{} already exists", path.display()))
+            } else if !create && !path.exists() {
+                Some(format!("{} does not exist", path.display()))
+            } else {
+                let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                let res = if rle_dir {
+                    std::fs::create_dir_all(&path)
+                        .map_err(db::Error::from)
+                        .and_then(|_| gd.open_archive(&path))
+                } else {
+                    gd.open_archive(&path)
+                };
+                res.err().map(|e| e.to_string())
+            };
+            match err {
+                Some(e) => siv.add_layer(Dialog::info(e)),
+                None => {
+                    siv.pop_layer();
+                    _leave_dialog(siv);
+                }
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    let edit = _labeled_edit("Path", "archive_path", path.to_string_lossy().into_owned());
+    let others: Vec<String> = _archives_in(&dir)
+        .into_iter()
+        .filter(|p| Path::new(p) != current)
+        .collect();
+    if create {
+        let mut kind: SelectView<bool> = SelectView::new().popup();
+        kind.add_item("SQLite database", false);
+        kind.add_item("Directory of RLE files", true);
+        dlg.set_content(
+            LinearLayout::vertical()
+                .child(edit)
+                .child(kind.with_name("archive_kind")),
+        );
+    } else if others.is_empty() {
+        dlg.set_content(edit);
+    } else {
+        let mut select: SelectView = SelectView::new();
+        select.add_all_str(others);
+        select.set_on_select(|siv, p: &String| {
+            siv.call_on_name("archive_path", |view: &mut EditView| view.set_content(p));
+        });
+        dlg.set_content(
+            LinearLayout::vertical()
+                .child(edit)
+                .child(TextView::new("\nArchives in the same directory:"))
+                .child(select.scrollable().max_height(10)),
+        );
+    }
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Directory of the open archive, where bundles go by default
+fn _archive_dir(siv: &mut Cursive) -> PathBuf {
+    let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+    gd.db_path
+        .parent()
+        .map(|d| d.to_path_buf())
+        .unwrap_or_default()
+}
+
+/// Writes the whole archive to a JSON or zip bundle
+fn _export(siv: &mut Cursive) {
+    let path = _archive_dir(siv).join("patterns.json");
+    let dlg = Dialog::new()
+        .title("Export archive")
+        .content(
+            LinearLayout::vertical()
+                .child(_labeled_edit(
+                    "File",
+                    "bundle_path",
+                    path.to_string_lossy().into_owned(),
+                ))
+                .child(TextView::new(
+                    "A .zip file holds one RLE file per record, anything else is JSON",
+                )),
+        )
+        .button("Export", |siv| {
+            let path = PathBuf::from(_get_edit(siv, "bundle_path").trim());
+            let res = {
+                let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+                bundle::export(gd.storage.as_ref(), &path)
+            };
+            match res {
+                Ok(n) => {
+                    siv.pop_layer();
+                    siv.add_layer(
+                        Dialog::text(format!("{} records written to {}", n, path.display()))
+                            .title("Export archive")
+                            .button("Ok", |s| {
+                                s.pop_layer();
+                                _leave_dialog(s);
+                            }),
+                    );
+                }
+                Err(e) => _error(siv, e),
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Dialog asking for the file to import and what to do with the records
+/// whose names are taken, then reporting what `import` did.
+fn _import_dialog(
+    siv: &mut Cursive,
+    title: &'static str,
+    label: &str,
+    path: PathBuf,
+    import: fn(&mut dyn PatternStore, &Path, bundle::Conflict) -> Dbres<bundle::ImportReport>,
+) {
+    let mut conflict: SelectView<bundle::Conflict> = SelectView::new().popup();
+    conflict.add_item("Skip records with taken names", bundle::Conflict::Skip);
+    conflict.add_item("Import them under a new name", bundle::Conflict::Rename);
+    conflict.add_item("Overwrite the existing ones", bundle::Conflict::Overwrite);
+    let dlg = Dialog::new()
+        .title(title)
+        .content(
+            LinearLayout::vertical()
+                .child(_labeled_edit(
+                    label,
+                    "import_path",
+                    path.to_string_lossy().into_owned(),
+                ))
+                .child(conflict.with_name("import_conflict")),
+        )
+        .button("Import", move |siv| {
+            let path = PathBuf::from(_get_edit(siv, "import_path").trim());
+            let conflict = *siv
+                .call_on_name(
+                    "import_conflict",
+                    |view: &mut SelectView<bundle::Conflict>| view.selection(),
+                )
+                .unwrap()
+                .unwrap();
+            let res = {
+                let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                import(gd.storage.as_mut(), &path, conflict)
+            };
+            match res {
+                Ok(r) => {
+                    let mut text = format!(
+                        "Added {}, renamed {}, overwritten {}, skipped {}",
+                        r.added, r.renamed, r.overwritten, r.skipped
+                    );
+                    if !r.failed.is_empty() {
+                        write(&mut text, format_args!("\n\nCould not read:")).unwrap();
+                        for f in r.failed.iter() {
+                            write(&mut text, format_args!("\n{}", f)).unwrap();
+                        }
+                    }
+                    siv.pop_layer();
+                    siv.add_layer(
+                        Dialog::around(TextView::new(text).scrollable().max_height(20))
+                            .title(title)
+                            .button("Ok", |s| {
+                                s.pop_layer();
+                                _leave_dialog(s);
+                            }),
+                    );
+                }
+                Err(e) => _error(siv, e),
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Merges the records of a bundle into the archive
+fn _import(siv: &mut Cursive) {
+    let path = _archive_dir(siv).join("patterns.json");
+    _import_dialog(siv, "Import archive", "File", path, bundle::import);
+}
+
+/// Imports pattern files (RLE, plaintext, Life 1.05/1.06), or a directory
+/// of them, as records
+fn _import_files(siv: &mut Cursive) {
+    let path = _archive_dir(siv);
+    _import_dialog(
+        siv,
+        "Import pattern files",
+        "File or dir",
+        path,
+        formats::import_path,
+    );
+}
+
+/// Opens a macrocell file in place of the field, without going through the
+/// archive
+fn _open_macrocell(siv: &mut Cursive) {
+    let path = _archive_dir(siv);
+    let dlg = Dialog::new()
+        .title("Open macrocell file")
+        .content(_labeled_edit(
+            "File",
+            "macrocell_path",
+            path.to_string_lossy().into_owned(),
+        ))
+        .button("Open", |siv| {
+            let path = PathBuf::from(_get_edit(siv, "macrocell_path").trim());
+            let tree = std::fs::read_to_string(&path)
+                .map_err(db::Error::from)
+                .and_then(|text| macrocell::Tree::parse(&text).map_err(db::Error::Corrupted));
+            match tree {
+                Ok(tree) => {
+                    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                    {
+                        let mut gd =
+                            (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                        gd.open_macrocell(&name, tree);
+                    }
+                    siv.pop_layer();
+                    _leave_dialog(siv);
+                }
+                Err(e) => _error(siv, e),
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Writes the field to a pattern file
+fn _export_pattern(siv: &mut Cursive) {
+    let name = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        if gd.name.is_empty() {
+            String::from("pattern")
+        } else {
+            gd.name.clone()
+        }
+    };
+    let path = _archive_dir(siv).join(store::file_name(&name));
+    let mut format: SelectView<formats::Format> = SelectView::new().popup();
+    for f in formats::ALL {
+        format.add_item(f.name(), f);
+    }
+    format.set_on_submit(|siv, f: &formats::Format| {
+        let ext = f.extension();
+        siv.call_on_name("pattern_path", |view: &mut EditView| {
+            let path = PathBuf::from(view.get_content().as_str()).with_extension(ext);
+            view.set_content(path.to_string_lossy());
+        });
+    });
+    let dlg = Dialog::new()
+        .title("Export pattern")
+        .content(
+            LinearLayout::vertical()
+                .child(_labeled_edit(
+                    "File",
+                    "pattern_path",
+                    path.to_string_lossy().into_owned(),
+                ))
+                .child(format.with_name("pattern_format")),
+        )
+        .button("Export", move |siv| {
+            let path = PathBuf::from(_get_edit(siv, "pattern_path").trim());
+            let format = *siv
+                .call_on_name(
+                    "pattern_format",
+                    |view: &mut SelectView<formats::Format>| view.selection(),
+                )
+                .unwrap()
+                .unwrap();
+            let text = {
+                let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                let meta = gd.record_meta();
+                match gd.tree.as_mut() {
+                    Some(tree) if format == formats::Format::Macrocell => {
+                        tree.rule = meta.rule;
+                        tree.description = meta.description;
+                        Ok(tree.write())
+                    }
+                    Some(_) => Err(db::Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "Only the macrocell format can hold a pattern this large",
+                    ))),
+                    None => formats::write_pattern(format, &name, &gd.universe.cells, &meta),
+                }
+            };
+            match text.and_then(|text| Ok(std::fs::write(&path, text)?)) {
+                Ok(_) => {
+                    siv.pop_layer();
+                    _leave_dialog(siv);
+                }
+                Err(e) => _error(siv, e),
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Where to export the current pattern, by default
+fn _export_path(siv: &mut Cursive, extension: &str) -> PathBuf {
+    let name = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        if gd.name.is_empty() {
+            String::from("pattern")
+        } else {
+            gd.name.clone()
+        }
+    };
+    _archive_dir(siv)
+        .join(store::file_name(&name))
+        .with_extension(extension)
+}
+
+/// Cell size and colour edits named `{prefix}_cell`, `{prefix}_alive` and
+/// `{prefix}_dead`
+fn _style_edits(content: LinearLayout, prefix: &str, style: &render::Style) -> LinearLayout {
+    content
+        .child(_labeled_edit(
+            "Cell, px",
+            &format!("{}_cell", prefix),
+            style.cell_size.to_string(),
+        ))
+        .child(_labeled_edit(
+            "Alive colour",
+            &format!("{}_alive", prefix),
+            render::format_colour(style.alive),
+        ))
+        .child(_labeled_edit(
+            "Dead colour",
+            &format!("{}_dead", prefix),
+            render::format_colour(style.dead),
+        ))
+}
+
+/// Reads the edits of `_style_edits`, and the grid colour when the
+/// `{prefix}_grid` checkbox exists and is checked
+fn _read_style(siv: &mut Cursive, prefix: &str) -> Option<render::Style> {
+    let edit = |siv: &mut Cursive, name: &str| _get_edit(siv, &format!("{}_{}", prefix, name));
+    let cell_size = edit(siv, "cell").trim().parse::<u16>().ok()?;
+    let grid = siv
+        .call_on_name(&format!("{}_grid", prefix), |view: &mut Checkbox| {
+            view.is_checked()
+        })
+        .unwrap_or(false);
+    if cell_size == 0 {
+        return None;
+    }
+    Some(render::Style {
+        cell_size,
+        alive: render::parse_colour(&edit(siv, "alive"))?,
+        dead: render::parse_colour(&edit(siv, "dead"))?,
+        grid: match grid {
+            true => Some(render::parse_colour(&edit(siv, "grid_colour"))?),
+            false => None,
+        },
+        ..Default::default()
+    })
+}
+
+fn _invalid_parameters(siv: &mut Cursive, text: &str) {
+    siv.add_layer(
+        Dialog::around(TextView::new(text))
+            .title("Invalid parameters")
+            .dismiss_button("Ok"),
+    );
+}
+
+fn _read_animation(
+    siv: &mut Cursive,
+    selection: Option<(i32, i32, i32, i32)>,
+) -> Option<render::Animation> {
+    let num = |siv: &mut Cursive, name: &str| _get_edit(siv, name).trim().parse::<i64>().ok();
+    let (from, to) = (num(siv, "anim_from")?, num(siv, "anim_to")?);
+    let delay = u16::try_from(num(siv, "anim_delay")? / 10).ok()?;
+    let style = _read_style(siv, "anim")?;
+    let checked = |siv: &mut Cursive, name: &str| {
+        siv.call_on_name(name, |view: &mut Checkbox| view.is_checked())
+            .unwrap_or(false)
+    };
+    if delay == 0 || to < from {
+        return None;
+    }
+    Some(render::Animation {
+        from,
+        to,
+        region: selection.filter(|_| checked(siv, "anim_selection")),
+        style,
+        delay,
+        counter: checked(siv, "anim_counter"),
+    })
+}
+
+/// Renders generations of the field to an animated GIF in the background
+fn _export_animation(siv: &mut Cursive) {
+    let (generation, selection) = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        (gd.universe.generation, gd.selection())
+    };
+    let path = _export_path(siv, "gif");
+    let content = LinearLayout::vertical()
+        .child(_labeled_edit(
+            "File",
+            "anim_path",
+            path.to_string_lossy().into_owned(),
+        ))
+        .child(_labeled_edit(
+            "From gen",
+            "anim_from",
+            generation.to_string(),
+        ))
+        .child(_labeled_edit(
+            "To gen",
+            "anim_to",
+            (generation + 100).to_string(),
+        ));
+    let mut content = _style_edits(content, "anim", &render::Style::default())
+        .child(_labeled_edit(
+            "Delay, ms",
+            "anim_delay",
+            String::from("100"),
+        ))
+        .child(
+            LinearLayout::horizontal()
+                .child(Checkbox::new().checked().with_name("anim_counter"))
+                .child(TextView::new(" Generation counter")),
+        );
+    if selection.is_some() {
+        content.add_child(
+            LinearLayout::horizontal()
+                .child(Checkbox::new().checked().with_name("anim_selection"))
+                .child(TextView::new(" Only the selection")),
+        );
+    }
+    let dlg = Dialog::new()
+        .title("Export animation")
+        .content(content)
+        .button("Export", move |siv| {
+            let path = PathBuf::from(_get_edit(siv, "anim_path").trim());
+            let anim = match _read_animation(siv, selection) {
+                Some(anim) => anim,
+                None => {
+                    _invalid_parameters(
+                        siv,
+                        "Generations must be numbers in order, cell size positive, \
+                         delay at least 10 ms, colours as #rrggbb",
+                    );
+                    return;
+                }
+            };
+            let u = {
+                let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+                gd.universe.clone()
+            };
+            let steps = anim.steps(u.generation).max(1);
+            siv.pop_layer();
+            let cb = siv.cb_sink().clone();
+            siv.add_layer(Dialog::around(
+                ProgressBar::new()
+                    .range(0, steps)
+                    .with_task(move |counter| {
+                        let res = render::export_gif(u, &anim, &path, &mut || counter.tick(1));
+                        cb.send(Box::new(move |s: &mut Cursive| {
+                            s.pop_layer();
+                            _leave_dialog(s);
+                            if let Err(e) = res {
+                                _error(s, e.into());
+                            }
+                        }))
+                        .unwrap();
+                    })
+                    .full_width(),
+            ));
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Writes the field, or a part of it, as a PNG or SVG image
+fn _export_image(siv: &mut Cursive) {
+    let (whole, viewport, selection) = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        let (x, y, w, h) = universe::bbox(&gd.universe.cells);
+        ((x - 1, y - 1, w + 2, h + 2), gd.viewport(), gd.selection())
+    };
+    let path = _export_path(siv, "png");
+    let mut region: SelectView<(i32, i32, i32, i32)> = SelectView::new().popup();
+    region.add_item("Whole pattern", whole);
+    region.add_item("Visible part", viewport);
+    if let Some(sel) = selection {
+        region.add_item("Selection", sel);
+        region.set_selection(2);
+    }
+    let style = render::Style {
+        cell_size: 8,
+        .._theme()
+    };
+    let content = LinearLayout::vertical()
+        .child(_labeled_edit(
+            "File",
+            "image_path",
+            path.to_string_lossy().into_owned(),
+        ))
+        .child(region.with_name("image_region"));
+    let dlg = Dialog::new()
+        .title("Export image")
+        .content(
+            _style_edits(content, "image", &style)
+                .child(
+                    LinearLayout::horizontal()
+                        .child(Checkbox::new().with_name("image_grid"))
+                        .child(TextView::new(" Grid lines")),
+                )
+                .child(_labeled_edit(
+                    "Grid colour",
+                    "image_grid_colour",
+                    render::format_colour(style.grid.unwrap()),
+                )),
+        )
+        .button("Export", |siv| {
+            let path = PathBuf::from(_get_edit(siv, "image_path").trim());
+            let region = *siv
+                .call_on_name(
+                    "image_region",
+                    |view: &mut SelectView<(i32, i32, i32, i32)>| view.selection(),
+                )
+                .unwrap()
+                .unwrap();
+            let style = match _read_style(siv, "image") {
+                Some(style) => style,
+                None => {
+                    _invalid_parameters(siv, "Cell size must be positive, colours as #rrggbb");
+                    return;
+                }
+            };
+            let res = {
+                let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+                render::export_image(&gd.universe.cells, region, &style, &path)
+            };
+            match res {
+                Ok(_) => {
+                    siv.pop_layer();
+                    _leave_dialog(siv);
+                }
+                Err(e) => _error(siv, e.into()),
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Message shown outside of any other dialog
+fn _notice(siv: &mut Cursive, title: &str, text: String) {
+    _enter_dialog(siv);
+    siv.add_layer(Dialog::text(text).title(title).button("Ok", |s| {
+        s.pop_layer();
+        _leave_dialog(s);
+    }));
+}
+
+/// Tells that a quadtree pattern cannot be changed that way, if it is one
+fn _refuse_tree(siv: &mut Cursive, what: &str) -> bool {
+    let tree_mode = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        gd.tree.is_some()
+    };
+    if tree_mode {
+        _notice(
+            siv,
+            what,
+            String::from("The pattern is too large for the field; it can only be run and exported as a macrocell file."),
+        );
+    }
+    tree_mode
+}
+
+/// Asks for the file of a recording, then starts it with `start`
+fn _ask_recording(
+    siv: &mut Cursive,
+    title: &'static str,
+    file: &str,
+    start: fn(&mut Cursive, &Path) -> std::io::Result<()>,
+) {
+    let path = _archive_dir(siv).join(file);
+    let dlg = Dialog::new()
+        .title(title)
+        .content(_labeled_edit(
+            "File",
+            "record_path",
+            path.to_string_lossy().into_owned(),
+        ))
+        .button("Start", move |siv| {
+            let path = PathBuf::from(_get_edit(siv, "record_path").trim());
+            match start(siv, &path) {
+                Ok(_) => {
+                    siv.pop_layer();
+                    _leave_dialog(siv);
+                }
+                Err(e) => _error(siv, e.into()),
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Starts recording the terminal as an asciicast, or stops the recording
+fn _record_session(siv: &mut Cursive) {
+    let handle = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        Rc::clone(&gd.session)
+    };
+    let running = handle.borrow_mut().take();
+    match running {
+        Some(session) => {
+            let path = session.path.clone();
+            match session.stop() {
+                Ok(_) => _notice(
+                    siv,
+                    "Record session",
+                    format!("Session recorded to {}", path.display()),
+                ),
+                Err(e) => _notice(siv, "Error", db::Error::from(e).to_string()),
+            }
+        }
+        None => _ask_recording(siv, "Record session", "session.cast", |siv, path| {
+            let session = recorder::Session::start(path, siv.screen_size())?;
+            let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+            *gd.session.borrow_mut() = Some(session);
+            Ok(())
+        }),
+    }
+}
+
+/// Starts writing the field as text frames, or stops
+fn _record_frames(siv: &mut Cursive) {
+    let running = {
+        let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+        gd.frames.take()
+    };
+    match running {
+        Some(mut log) => {
+            let res = match log.error {
+                Some(e) => Err(e),
+                None => log.recorder.flush(),
+            };
+            match res {
+                Ok(_) => _notice(
+                    siv,
+                    "Record field frames",
+                    format!(
+                        "{} frames recorded to {}",
+                        log.recorder.frames,
+                        log.path.display()
+                    ),
+                ),
+                Err(e) => _notice(siv, "Error", db::Error::from(e).to_string()),
+            }
+        }
+        None => _ask_recording(siv, "Record field frames", "frames.txt", |siv, path| {
+            let recorder = cast::FrameRecorder::create(path)?;
+            let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+            gd.frames = Some(FrameLog {
+                path: path.to_path_buf(),
+                recorder,
+                error: None,
+            });
+            // The starting position
+            gd.record_frame();
+            Ok(())
+        }),
+    }
+}
+
+/// Draws the graph of the series chosen, values on the left and
+/// generations on the bottom line
+fn _draw_graph(state: &(Rc<RefCell<Gamedata>>, Rc<Cell<Series>>), p: &Printer) {
+    let gd = state.0.borrow();
+    let series = state.1.get();
+    let samples = gd.history.samples();
+    if samples.is_empty() {
+        p.print((0, 0), "Nothing recorded yet: run the field to plot it");
+        return;
+    }
+    let values: Vec<f64> = samples.iter().map(|s| series.value(s)).collect();
+    let top = values.iter().cloned().fold(0.0, f64::max);
+    let top_label = top.to_string();
+    let margin = top_label.len() + 1;
+    let (w, h) = (p.size.x.saturating_sub(margin), p.size.y.saturating_sub(1));
+    for (y, row) in history::plot(&values, top, w, h).iter().enumerate() {
+        p.print((margin, y), row);
+    }
+    p.print((0, 0), &top_label);
+    p.print((margin - 2, h.saturating_sub(1)), "0");
+    let first = samples[0].generation.to_string();
+    let last = samples[samples.len() - 1].generation.to_string();
+    p.print((margin, h), &first);
+    p.print((p.size.x.saturating_sub(last.len()), h), &last);
+}
+
+/// Asks for a file, then writes the history to it as CSV
+fn _export_history(siv: &mut Cursive) {
+    let path = _archive_dir(siv).join("history.csv");
+    let dlg = Dialog::new()
+        .title("Export CSV")
+        .content(_labeled_edit(
+            "File",
+            "csv_path",
+            path.to_string_lossy().into_owned(),
+        ))
+        .button("Export", |siv| {
+            let path = PathBuf::from(_get_edit(siv, "csv_path").trim());
+            let res = {
+                let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+                gd.history.save_csv(&path)
+            };
+            match res {
+                Ok(_) => {
+                    siv.pop_layer();
+                }
+                Err(e) => _error(siv, e.into()),
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+        });
+    siv.add_layer(dlg);
+}
+
+/// Population, bounding box area, births or deaths over the generations
+fn _show_graph(siv: &mut Cursive) {
+    let gd = Rc::clone(siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap());
+    let series = Rc::new(Cell::new(Series::Population));
+    let mut choice: SelectView<Series> = SelectView::new().popup();
+    for s in Series::ALL {
+        choice.add_item(s.name(), s);
+    }
+    let chosen = Rc::clone(&series);
+    choice.set_on_submit(move |_, s: &Series| chosen.set(*s));
+    let dlg = Dialog::new()
+        .title("Graph")
+        .content(
+            LinearLayout::vertical().child(choice).child(
+                Canvas::new((gd, series))
+                    .with_draw(_draw_graph)
+                    .fixed_size((72, 16)),
+            ),
+        )
+        .button("Export CSV...", _export_history)
+        .button("Close", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    _enter_dialog(siv);
+    siv.add_layer(dlg);
+}
+
+/// Picks a record, then asks for a new name and applies the action (rename
+/// or duplicate) to both names; `verb` is only shown in the dialogs.
+fn _rename_or_copy(
+    siv: &mut Cursive,
+    verb: &'static str,
+    action: fn(&mut Gamedata, &str, &str) -> Dbres<()>,
+) {
+    let mut select = match _record_select(siv) {
+        Ok(select) => select,
+        Err(e) => return _error(siv, e),
+    };
+    select.set_on_submit(move |siv, name: &str| {
+        siv.pop_layer();
+        let name = name.to_string();
+        let dlg = Dialog::new()
+            .title(format!("{} '{}' as", verb, name))
+            .content(
+                EditView::new()
+                    .content(name.clone())
+                    .with_name("new_name")
+                    .min_width(20),
+            )
+            .button("Ok", move |siv| {
+                let new_name = _get_edit(siv, "new_name");
+                let res = {
+                    let mut gd = siv
+                        .user_data::<Rc<RefCell<Gamedata>>>()
+                        .unwrap()
+                        .borrow_mut();
+                    action(&mut gd, &name, &new_name)
+                };
+                match res {
+                    Ok(_) => {
+                        siv.pop_layer();
+                        _leave_dialog(siv);
+                    }
+                    Err(e) => _error(siv, e),
+                }
+            })
+            .button("Cancel", |siv| {
+                siv.pop_layer();
+                _leave_dialog(siv);
+            });
+        siv.add_layer(dlg);
+    });
+
+    siv.add_layer(
+        Dialog::around(select.scrollable())
+            .title(format!("Select a position to {}", verb.to_lowercase()))
+            .button("Cancel", |s| {
+                s.pop_layer();
+                _leave_dialog(s);
+            }),
+    );
+    _enter_dialog(siv);
+}
+
+fn _overwrite(siv: &mut Cursive, name: Rc<String>) {
+    siv.add_layer(
+        Dialog::around(TextView::new(format!(
+            "A position named '{}' exists. Overwrite it?",
+            &name
+        )))
+        .title("Overwrite")
+        .button("Overwrite", move |siv| {
+            let res = {
+                let mut gd = siv
+                    .user_data::<Rc<RefCell<Gamedata>>>()
+                    .unwrap()
+                    .borrow_mut();
+                gd.overwrite(&name)
+            };
+            // The confirmation
+            siv.pop_layer();
+            _saved(siv, res);
+        })
+        .dismiss_button("Cancel"),
+    );
+}
+
+/// Closes the save dialog if the save went through, reports the error
+/// otherwise.
+fn _saved(siv: &mut Cursive, res: Dbres<()>) {
+    match res {
+        Ok(_) => {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        }
+        Err(e) => _error(siv, e),
+    }
+}
+
+fn _do_save(siv: &mut Cursive, name: &str) {
+    let res = {
+        let mut gd = siv
+            .user_data::<Rc<RefCell<Gamedata>>>()
+            .unwrap()
+            .borrow_mut();
+        gd.save(name)
+    };
+    _saved(siv, res);
+}
+
+fn _duplicates(siv: &mut Cursive) {
+    let mut s = String::new();
+    let groups = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        gd.storage
+            .duplicates(false)
+            .and_then(|identical| Ok((identical, gd.storage.duplicates(true)?)))
+    };
+    match groups {
+        Err(e) => return _error(siv, e),
+        Ok((identical, oriented)) => {
+            for group in identical.iter() {
+                write(&mut s, format_args!("Identical: {}\n", group.join(", "))).unwrap();
+            }
+            for group in oriented.iter().filter(|g| !identical.contains(g)) {
+                write(
+                    &mut s,
+                    format_args!("Rotated or reflected: {}\n", group.join(", ")),
+                )
+                .unwrap();
+            }
+        }
+    }
+    if s.is_empty() {
+        s.push_str("No duplicates found");
+    }
+    _enter_dialog(siv);
+    siv.add_layer(
+        Dialog::around(TextView::new(s).scrollable().min_width(50))
+            .title("Duplicate patterns")
+            .button("Ok", |s| {
+                s.pop_layer();
+                _leave_dialog(s);
+            }),
+    );
+}
+
+fn _search_results(siv: &mut Cursive, found: Vec<(String, Vec<search::Match>)>) {
+    let mut select: SelectView<(String, search::Match)> = SelectView::new();
+    for (name, matches) in found {
+        for m in matches {
+            let mut label = format!(
+                "{}: ({},{}) {}",
+                name,
+                m.x,
+                m.y,
+                patterns::ORIENTATIONS[m.orientation]
+            );
+            if m.generation > 0 {
+                write(&mut label, format_args!(", generation +{}", m.generation)).unwrap();
+            }
+            select.add_item(label, (name.clone(), m));
+        }
+    }
+    let dlg = if select.is_empty() {
+        Dialog::text("No record contains the pattern")
+    } else {
+        select.set_on_submit(|siv, (name, m): &(String, search::Match)| {
+            let res = {
+                let mut gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                gd.show_match(name, m)
+            };
+            match res {
+                Ok(_) => {
+                    siv.pop_layer();
                     _leave_dialog(siv);
                 }
+                Err(e) => _error(siv, e),
+            }
+        });
+        Dialog::around(select.scrollable().min_width(50).max_height(20))
+    };
+    siv.add_layer(dlg.title("Search results").button("Close", |s| {
+        s.pop_layer();
+        _leave_dialog(s);
+    }));
+}
+
+/// Looks for the query in the records in the background, then shows what
+/// was found.
+fn _search_task(
+    siv: &mut Cursive,
+    records: Vec<(String, HashSet<(i32, i32)>)>,
+    query: Vec<(i32, i32)>,
+    oriented: bool,
+    generations: i64,
+    rule: rule::Rule,
+) {
+    let cb = siv.cb_sink().clone();
+    siv.add_layer(Dialog::around(
+        ProgressBar::new()
+            .range(0, records.len())
+            .with_task(move |counter| {
+                let mut found: Vec<(String, Vec<search::Match>)> = vec![];
+                for (name, cells) in records {
+                    let matches =
+                        search::find_evolving(&cells, &query, oriented, generations, &|f| {
+                            rule.step(f)
+                        });
+                    if !matches.is_empty() {
+                        found.push((name, matches));
+                    }
+                    counter.tick(1);
+                }
+                cb.send(Box::new(move |s: &mut Cursive| {
+                    s.pop_layer();
+                    _search_results(s, found);
+                }))
+                .unwrap();
+            })
+            .full_width(),
+    ));
+    siv.set_autorefresh(true);
+}
+
+/// Searches the archive for the live cells of the selection
+fn _search(siv: &mut Cursive) {
+    let query = {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        gd.selected_cells()
+    };
+    _enter_dialog(siv);
+    if query.is_empty() {
+        siv.add_layer(
+            Dialog::text("Select the pattern to search for first (<m> in the edit mode)")
+                .title("Search the archive")
+                .button("Ok", |s| {
+                    s.pop_layer();
+                    _leave_dialog(s);
+                }),
+        );
+        return;
+    }
+    let query = Rc::new(query);
+    let q = Rc::clone(&query);
+    let dlg = Dialog::new()
+        .title("Search the archive")
+        .content(
+            LinearLayout::vertical()
+                .child(TextView::new(patterns::preview(&query, 40, 16)))
+                .child(
+                    LinearLayout::horizontal()
+                        .child(Checkbox::new().checked().with_name("search_oriented"))
+                        .child(TextView::new(" Also rotated or reflected")),
+                )
+                .child(_labeled_edit(
+                    "Generations",
+                    "search_generations",
+                    String::from("0"),
+                )),
+        )
+        .button("Search", move |siv| {
+            let oriented = siv
+                .call_on_name("search_oriented", |view: &mut Checkbox| view.is_checked())
+                .unwrap();
+            let generations = match _get_edit(siv, "search_generations").trim().parse::<i64>() {
+                Ok(n) if n >= 0 => n,
                 _ => {
-                    siv.add_layer(
-                        Dialog::around(TextView::new(format!("Is the name '{}' unique?", &text)))
-                            .title("Cannot save")
-                            .dismiss_button("Ok"),
-                    );
+                    siv.add_layer(Dialog::info("Generations must be a non-negative number"));
+                    return;
+                }
+            };
+            let (records, rule) = {
+                let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+                let records = gd.storage.list().and_then(|names| {
+                    let mut res: Vec<(String, HashSet<(i32, i32)>)> = vec![];
+                    for name in names {
+                        let cells = gd.storage.load(&name)?.into_iter().collect();
+                        res.push((name, cells));
+                    }
+                    Ok(res)
+                });
+                (records, gd.universe.rule)
+            };
+            match records {
+                Ok(records) => {
+                    siv.pop_layer();
+                    _search_task(siv, records, q.to_vec(), oriented, generations, rule);
+                }
+                Err(e) => _error(siv, e),
+            }
+        })
+        .button("Cancel", |siv| {
+            siv.pop_layer();
+            _leave_dialog(siv);
+        });
+    siv.add_layer(dlg);
+}
+
+fn _save(siv: &mut Cursive) {
+    if _refuse_tree(siv, "Save") {
+        return;
+    }
+    let (name, description, author, tags, rule, info);
+    {
+        let gd = (*siv.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow();
+        name = gd.name.clone();
+        description = gd.meta.description.clone();
+        author = if gd.meta.author.is_empty() {
+            std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .unwrap_or_default()
+        } else {
+            gd.meta.author.clone()
+        };
+        tags = gd.meta.tags.join(", ");
+        rule = gd.meta.rule.clone();
+        info = format!(
+            "Generation {}, population {}",
+            gd.universe.generation,
+            gd.universe.cells.len()
+        );
+    }
+    let dlg = Dialog::new()
+        .content(
+            LinearLayout::vertical()
+                .child(_labeled_edit("Name", "save_name", name))
+                .child(_labeled_edit(
+                    "Description",
+                    "save_description",
+                    description,
+                ))
+                .child(_labeled_edit("Author", "save_author", author))
+                .child(_labeled_edit("Tags", "save_tags", tags))
+                .child(_labeled_edit("Rule", "save_rule", rule))
+                .child(TextView::new(info)),
+        )
+        .button("Ok", |siv| {
+            let checks;
+            let text = _get_edit(siv, "save_name");
+            let description = _get_edit(siv, "save_description");
+            let author = _get_edit(siv, "save_author");
+            let tags = _get_edit(siv, "save_tags");
+            let rule = match rule::Rule::parse(&_get_edit(siv, "save_rule")) {
+                Ok(r) => r,
+                Err(e) => {
+                    siv.add_layer(Dialog::info(e).title("Invalid rule"));
+                    return;
                 }
+            };
+            {
+                let mut gd = siv
+                    .user_data::<Rc<RefCell<Gamedata>>>()
+                    .unwrap()
+                    .borrow_mut();
+                gd.meta.description = description.trim().to_string();
+                gd.meta.author = author.trim().to_string();
+                gd.meta.rule = rule.to_string();
+                gd.universe.rule = rule;
+                gd.meta.tags = tags
+                    .split(',')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect();
+                checks = gd.storage.exists(&text).and_then(|exists| {
+                    Ok((
+                        exists,
+                        gd.storage.find_equivalent(&gd.universe.cells, true)?,
+                    ))
+                });
+            }
+            let (exists, equivalent) = match checks {
+                Ok(res) => res,
+                Err(e) => return _error(siv, e),
+            };
+            if exists {
+                _overwrite(siv, text);
+            } else if !equivalent.is_empty() {
+                siv.add_layer(
+                    Dialog::around(TextView::new(format!(
+                        "The same pattern, possibly moved, rotated or reflected, \
+                         is already saved as: {}",
+                        equivalent.join(", ")
+                    )))
+                    .title("Duplicate pattern")
+                    .button("Save anyway", move |siv| {
+                        siv.pop_layer();
+                        _do_save(siv, &text);
+                    })
+                    .dismiss_button("Cancel"),
+                );
+            } else {
+                _do_save(siv, &text);
             }
         })
         .button("Cancel", |siv| {
@@ -617,15 +2921,63 @@ fn _draw_status(gd: &Rc<RefCell<Gamedata>>, p: &Printer) {
         ),
     )
     .unwrap();
+    write(&mut s, format_args!("; GEN={}", gdata.universe.generation)).unwrap();
+    if gdata.universe.rule != rule::LIFE {
+        write(&mut s, format_args!("; RULE={}", gdata.universe.rule)).unwrap();
+    }
+    if gdata.tree.is_some() {
+        s.push_str("; QUADTREE");
+    }
+    if let Some((_, _, w, h)) = gdata.selection() {
+        write(&mut s, format_args!("; SEL={}x{}", w, h)).unwrap();
+    }
+    if gdata.edit_mode {
+        write(&mut s, format_args!("; TOOL={}", gdata.tool.name())).unwrap();
+        if let Some((ax, ay)) = gdata.anchor {
+            write(&mut s, format_args!(" from ({},{})", ax, ay)).unwrap();
+        }
+    }
+    if gdata.view_mode != ViewMode::Plain {
+        write(&mut s, format_args!("; VIEW={}", gdata.view_mode.name())).unwrap();
+    }
+    if gdata.draw_symmetry != DrawSymmetry::Off {
+        let (cx2, cy2) = gdata.sym_centre2;
+        write(
+            &mut s,
+            format_args!(
+                "; SYM={} @({},{})",
+                gdata.draw_symmetry.name(),
+                cx2 as f32 / 2.0,
+                cy2 as f32 / 2.0
+            ),
+        )
+        .unwrap();
+    }
+    if let Some(f) = gdata.db_path.file_name() {
+        write(&mut s, format_args!("; DB={}", f.to_string_lossy())).unwrap();
+    }
+    if gdata.session.borrow().is_some() || gdata.frames.is_some() {
+        write(&mut s, format_args!("; REC")).unwrap();
+    }
     while s.len() <= x_max {
         write(&mut s, format_args!("       ")).unwrap();
     }
     p.print((0, 0), &s);
 }
 
-pub fn run() {
-    let mut siv = cursive::pancurses();
-    let gdata: Rc<RefCell<Gamedata>> = Rc::new(RefCell::new(Gamedata::new()));
+pub fn run(db_path: &Path) {
+    let gdata: Rc<RefCell<Gamedata>> = match Gamedata::new(db_path) {
+        Ok(gd) => Rc::new(RefCell::new(gd)),
+        Err(e) => {
+            eprintln!("Cannot open {}: {}", db_path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let session = Rc::clone(&gdata.borrow().session);
+    let mut siv = cursive::CursiveRunnable::new(move || {
+        cursive::backends::curses::pan::Backend::init()
+            .map(|b| recorder::Backend::wrap(b, Rc::clone(&session)))
+    });
     siv.set_user_data(Rc::clone(&gdata));
 
     siv.set_autohide_menu(false);
@@ -640,17 +2992,52 @@ pub fn run() {
                 .leaf("Save", |s| {
                     _save(s);
                 })
+                .leaf("Rename...", |s| {
+                    _rename_or_copy(s, "Rename", Gamedata::rename)
+                })
+                .leaf("Duplicate...", |s| {
+                    _rename_or_copy(s, "Duplicate", |gd, a, b| gd.storage.duplicate(a, b))
+                })
                 .leaf("Delete", |s| {
                     _delete(s);
-                }),
+                })
+                .delimiter()
+                .leaf("Find duplicates", _duplicates)
+                .leaf("Search selection...", _search)
+                .delimiter()
+                .leaf("Open archive...", |s| _archive(s, false))
+                .leaf("New archive...", |s| _archive(s, true))
+                .leaf("Export archive...", _export)
+                .leaf("Import archive...", _import)
+                .leaf("Import pattern files...", _import_files)
+                .leaf("Open macrocell file...", _open_macrocell)
+                .leaf("Export pattern...", _export_pattern)
+                .leaf("Export animation...", _export_animation)
+                .leaf("Export image...", _export_image),
         )
+        .add_subtree("Draw", {
+            let mut tree = menu::Tree::new();
+            for tool in draw::ALL {
+                tree.add_leaf(format!("{} <{}>", tool.name(), tool.key()), move |s| {
+                    let mut gd = (*s.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
+                    gd.set_tool(tool);
+                    gd.enter_edit_mode();
+                });
+            }
+            tree
+        })
         .add_subtree(
             "Tools",
             menu::Tree::new()
-                .leaf("Fast forward", |s| _run_multiple_steps(s))
+                .leaf("Fast forward", _run_multiple_steps)
+                .leaf("Random fill", _random_fill)
+                .leaf("Pattern library", _stamp)
+                .leaf("Graph...", _show_graph)
+                .leaf("Record session...", _record_session)
+                .leaf("Record field frames...", _record_frames)
                 .leaf("Clear", |s| {
                     let mut gd = (*s.user_data::<Rc<RefCell<Gamedata>>>().unwrap()).borrow_mut();
-                    gd.field.clear();
+                    gd.clear();
                 }),
         )
         .add_delimiter()
@@ -675,5 +3062,27 @@ pub fn run() {
 }
 
 fn main() {
-    run();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args
+        .first()
+        .is_some_and(|a| cli::COMMANDS.contains(&a.as_str()))
+    {
+        std::process::exit(cli::main(&args));
+    }
+    let db_path = match config::db_path(&args) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, config::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = config::prepare_dir(&db_path) {
+        eprintln!(
+            "Cannot create the directory of {}: {}",
+            db_path.display(),
+            e
+        );
+        std::process::exit(1);
+    }
+    run(&db_path);
 }
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::fmt::write;
+
+use crate::rle;
+
+/// Built-in patterns, name and RLE.
+pub const LIBRARY: &[(&str, &str)] = &[
+    ("Block", "2o$2o!"),
+    ("Beehive", "b2o$o2bo$b2o!"),
+    ("Loaf", "b2o$o2bo$bobo$2bo!"),
+    ("Boat", "2o$obo$bo!"),
+    ("Eater 1", "2o$obo$2bo$2b2o!"),
+    ("Blinker", "3o!"),
+    ("Toad", "b3o$3o!"),
+    ("Beacon", "2o$o$3bo$2b2o!"),
+    (
+        "Pulsar",
+        "2b3o3b3o2$o4bobo4bo$o4bobo4bo$o4bobo4bo$2b3o3b3o2$2b3o3b3o$o4bobo4bo$\
+         o4bobo4bo$o4bobo4bo2$2b3o3b3o!",
+    ),
+    ("Pentadecathlon", "2bo4bo$2ob4ob2o$2bo4bo!"),
+    ("Glider", "bo$2bo$3o!"),
+    ("LWSS", "bo2bo$o$o3bo$4o!"),
+    ("MWSS", "3bo$bo3bo$o$o4bo$5o!"),
+    ("HWSS", "3b2o$bo4bo$o$o5bo$6o!"),
+    (
+        "Gosper glider gun",
+        "24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b2o$2o8bo5bo3b2o$\
+         2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!",
+    ),
+    ("R-pentomino", "b2o$2o$bo!"),
+    ("B-heptomino", "ob2o$3o$bo!"),
+    ("Pi-heptomino", "3o$obo$obo!"),
+    ("Acorn", "bo$3bo$2o2b3o!"),
+    ("Diehard", "6bo$2o$bo3b3o!"),
+];
+
+pub fn get(name: &str) -> Option<Vec<(i32, i32)>> {
+    LIBRARY
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, text)| rle::parse(text).unwrap())
+}
+
+pub const ORIENTATIONS: [&str; 8] = [
+    "As is",
+    "Rotated 90",
+    "Rotated 180",
+    "Rotated 270",
+    "Flipped",
+    "Flipped, rotated 90",
+    "Flipped, rotated 180",
+    "Flipped, rotated 270",
+];
+
+/// Applies one of the eight orientations (see ORIENTATIONS) and moves the
+/// bounding box to the origin.
+pub fn orient(cells: &[(i32, i32)], orientation: usize) -> Vec<(i32, i32)> {
+    let res: Vec<(i32, i32)> = cells
+        .iter()
+        .map(|&(x, y)| {
+            let (x, y) = if orientation >= 4 { (-x, y) } else { (x, y) };
+            match orientation % 4 {
+                1 => (-y, x),
+                2 => (-x, -y),
+                3 => (y, -x),
+                _ => (x, y),
+            }
+        })
+        .collect();
+    let x0 = res.iter().map(|c| c.0).min().unwrap_or(0);
+    let y0 = res.iter().map(|c| c.1).min().unwrap_or(0);
+    res.into_iter().map(|(x, y)| (x - x0, y - y0)).collect()
+}
+
+/// The pattern moved to the origin with its cells sorted; with `oriented`,
+/// the smallest of its eight orientations, so that rotated and reflected
+/// copies give the same result.
+pub fn canonical(cells: &[(i32, i32)], oriented: bool) -> Vec<(i32, i32)> {
+    let n = if oriented { 8 } else { 1 };
+    (0..n)
+        .map(|o| {
+            let mut c = orient(cells, o);
+            c.sort_unstable();
+            c
+        })
+        .min()
+        .unwrap()
+}
+
+/// FNV-1a hash of the canonical form, as hex. Unlike the std hashers it is
+/// stable, so it can be stored. Empty patterns get an empty hash.
+pub fn pattern_hash(cells: &[(i32, i32)], oriented: bool) -> String {
+    if cells.is_empty() {
+        return String::new();
+    }
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for (x, y) in canonical(cells, oriented) {
+        for b in x.to_le_bytes().iter().chain(y.to_le_bytes().iter()) {
+            h ^= *b as u64;
+            h = h.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}", h)
+}
+
+/// Names of the patterns grouped by their canonical form, leaving out the
+/// patterns without a copy. The groups are in order of their canonical forms.
+pub fn group_equivalent(
+    records: Vec<(String, Vec<(i32, i32)>)>,
+    oriented: bool,
+) -> Vec<Vec<String>> {
+    let mut groups: BTreeMap<Vec<(i32, i32)>, Vec<String>> = BTreeMap::new();
+    for (name, cells) in records {
+        groups
+            .entry(canonical(&cells, oriented))
+            .or_default()
+            .push(name);
+    }
+    groups.into_values().filter(|g| g.len() > 1).collect()
+}
+
+/// Text rendering of the pattern fitting into max_w x max_h characters. When
+/// the pattern is larger, blocks of cells are shown as a single character,
+/// alive if any of the cells is.
+pub fn preview(cells: &[(i32, i32)], max_w: i32, max_h: i32) -> String {
+    if cells.is_empty() {
+        return String::from("(empty)");
+    }
+    let x0 = cells.iter().map(|c| c.0).min().unwrap();
+    let x1 = cells.iter().map(|c| c.0).max().unwrap();
+    let y0 = cells.iter().map(|c| c.1).min().unwrap();
+    let y1 = cells.iter().map(|c| c.1).max().unwrap();
+    let (w, h) = (x1 - x0 + 1, y1 - y0 + 1);
+    let scale = ((w + max_w - 1) / max_w)
+        .max((h + max_h - 1) / max_h)
+        .max(1);
+    let (pw, ph) = ((w + scale - 1) / scale, (h + scale - 1) / scale);
+    let mut grid = vec![vec![false; pw as usize]; ph as usize];
+    for (x, y) in cells.iter() {
+        grid[((y - y0) / scale) as usize][((x - x0) / scale) as usize] = true;
+    }
+    let mut s = String::new();
+    if scale > 1 {
+        write(&mut s, format_args!("(1:{})\n", scale)).unwrap();
+    }
+    for row in grid {
+        for c in row {
+            s.push(if c { 'O' } else { '.' });
+        }
+        s.push('\n');
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_library_populations() {
+        let expected = [
+            ("Block", 4),
+            ("Pulsar", 48),
+            ("Pentadecathlon", 12),
+            ("LWSS", 9),
+            ("MWSS", 11),
+            ("HWSS", 13),
+            ("Gosper glider gun", 36),
+            ("Acorn", 7),
+            ("Diehard", 7),
+        ];
+        for (name, pop) in expected {
+            assert_eq!(get(name).unwrap().len(), pop, "{}", name);
+        }
+        for (name, text) in LIBRARY {
+            assert!(rle::parse(text).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_orient() {
+        let glider = get("Glider").unwrap();
+        let mut r = orient(&glider, 1);
+        r.sort();
+        assert_eq!(r, vec![(0, 0), (0, 1), (0, 2), (1, 2), (2, 1)]);
+        assert_eq!(orient(&orient(&glider, 4), 4), glider);
+    }
+
+    #[test]
+    fn test_pattern_hash() {
+        let glider = get("Glider").unwrap();
+        let moved: Vec<(i32, i32)> = glider.iter().map(|(x, y)| (x - 7, y + 100)).collect();
+        let turned = orient(&glider, 3);
+        assert_eq!(pattern_hash(&glider, false), pattern_hash(&moved, false));
+        assert_ne!(pattern_hash(&glider, false), pattern_hash(&turned, false));
+        assert_eq!(pattern_hash(&glider, true), pattern_hash(&turned, true));
+        assert_ne!(
+            pattern_hash(&glider, true),
+            pattern_hash(&get("Boat").unwrap(), true)
+        );
+    }
+}
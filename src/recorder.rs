@@ -0,0 +1,253 @@
+use std::cell::{Cell, RefCell};
+use std::fmt::write;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use cursive::backend;
+use cursive::event::Event;
+use cursive::theme::{Color, ColorPair, Effect};
+use cursive::Vec2;
+use life_cursive::cast::CastWriter;
+
+/// Character of the screen with its colours and effects
+#[derive(Clone, Copy, PartialEq)]
+struct Glyph {
+    ch: char,
+    colors: ColorPair,
+    effects: u8,
+}
+
+/// Recording in progress of the terminal output
+pub struct Session {
+    pub path: PathBuf,
+    cast: CastWriter<BufWriter<File>>,
+    size: Vec2,
+    // Screen as last written to the recording, to write only the changes
+    shown: Vec<Option<Glyph>>,
+    // First write error, the recording stops there
+    pub error: Option<io::Error>,
+}
+
+impl Session {
+    pub fn start(path: &Path, size: Vec2) -> io::Result<Session> {
+        Ok(Session {
+            path: path.to_path_buf(),
+            cast: CastWriter::create(path, size.x, size.y)?,
+            size,
+            shown: vec![None; size.x * size.y],
+            error: None,
+        })
+    }
+
+    /// Flushes the recording, returns the first error
+    pub fn stop(mut self) -> io::Result<()> {
+        match self.error {
+            Some(e) => Err(e),
+            None => self.cast.flush(),
+        }
+    }
+}
+
+/// Shared by the backend and the UI, which starts and stops recordings
+pub type Handle = Rc<RefCell<Option<Session>>>;
+
+fn _effect_bit(e: Effect) -> u8 {
+    match e {
+        Effect::Simple => 0,
+        Effect::Reverse => 1,
+        Effect::Dim => 2,
+        Effect::Bold => 4,
+        Effect::Italic => 8,
+        Effect::Strikethrough => 16,
+        Effect::Underline => 32,
+        Effect::Blink => 64,
+    }
+}
+
+fn _sgr_color(s: &mut String, c: Color, background: bool) {
+    let base = if background { 10 } else { 0 };
+    match c {
+        Color::TerminalDefault => write(s, format_args!(";{}", 39 + base)),
+        Color::Dark(b) => write(s, format_args!(";{}", 30 + base + b as u8)),
+        Color::Light(b) => write(s, format_args!(";{}", 90 + base + b as u8)),
+        Color::Rgb(r, g, b) => write(s, format_args!(";{};2;{};{};{}", 38 + base, r, g, b)),
+        Color::RgbLowRes(r, g, b) => write(
+            s,
+            format_args!(";{};5;{}", 38 + base, 16 + 36 * r + 6 * g + b),
+        ),
+    }
+    .unwrap();
+}
+
+/// Escape sequence selecting the colours and effects
+fn _sgr(colors: ColorPair, effects: u8) -> String {
+    let mut s = String::from("\x1b[0");
+    for (bit, code) in [(1, 7), (2, 2), (4, 1), (8, 3), (16, 9), (32, 4), (64, 5)] {
+        if effects & bit != 0 {
+            write(&mut s, format_args!(";{}", code)).unwrap();
+        }
+    }
+    _sgr_color(&mut s, colors.front, false);
+    _sgr_color(&mut s, colors.back, true);
+    s.push('m');
+    s
+}
+
+/// Backend drawing through another one and keeping a copy of the screen,
+/// which is written to the session recording, if any, at every refresh.
+pub struct Backend {
+    inner: Box<dyn backend::Backend>,
+    session: Handle,
+    screen: RefCell<Vec<Glyph>>,
+    size: Cell<Vec2>,
+    colors: Cell<ColorPair>,
+    effects: Cell<u8>,
+}
+
+impl Backend {
+    pub fn wrap(inner: Box<dyn backend::Backend>, session: Handle) -> Box<dyn backend::Backend> {
+        let colors = ColorPair {
+            front: Color::TerminalDefault,
+            back: Color::TerminalDefault,
+        };
+        Box::new(Backend {
+            inner,
+            session,
+            screen: RefCell::new(vec![]),
+            size: Cell::new(Vec2::zero()),
+            colors: Cell::new(colors),
+            effects: Cell::new(0),
+        })
+    }
+
+    fn _blank(&self, colors: ColorPair) -> Glyph {
+        Glyph {
+            ch: ' ',
+            colors,
+            effects: 0,
+        }
+    }
+
+    fn _check_size(&self) {
+        let size = self.inner.screen_size();
+        if size != self.size.get() {
+            self.size.set(size);
+            *self.screen.borrow_mut() = vec![self._blank(self.colors.get()); size.x * size.y];
+        }
+    }
+
+    /// Writes the changes of the screen to the recording
+    fn _record(&self, session: &mut Session) -> io::Result<()> {
+        let size = self.size.get();
+        if session.size != size {
+            session.cast.resize(size.x, size.y)?;
+            session.size = size;
+            session.shown = vec![None; size.x * size.y];
+        }
+        let screen = self.screen.borrow();
+        let mut out = String::new();
+        let mut pen: Option<(ColorPair, u8)> = None;
+        let mut cursor: Option<usize> = None;
+        for (i, g) in screen.iter().enumerate() {
+            if session.shown[i] == Some(*g) {
+                continue;
+            }
+            if cursor != Some(i) {
+                write(
+                    &mut out,
+                    format_args!("\x1b[{};{}H", i / size.x + 1, i % size.x + 1),
+                )
+                .unwrap();
+            }
+            if pen != Some((g.colors, g.effects)) {
+                out.push_str(&_sgr(g.colors, g.effects));
+                pen = Some((g.colors, g.effects));
+            }
+            out.push(g.ch);
+            cursor = if (i + 1) % size.x == 0 {
+                None
+            } else {
+                Some(i + 1)
+            };
+            session.shown[i] = Some(*g);
+        }
+        session.cast.output(&out)
+    }
+}
+
+impl backend::Backend for Backend {
+    fn poll_event(&mut self) -> Option<Event> {
+        self.inner.poll_event()
+    }
+
+    fn set_title(&mut self, title: String) {
+        self.inner.set_title(title)
+    }
+
+    fn refresh(&mut self) {
+        self.inner.refresh();
+        self._check_size();
+        if let Some(session) = self.session.borrow_mut().as_mut() {
+            if session.error.is_none() {
+                if let Err(e) = self._record(session) {
+                    session.error = Some(e);
+                }
+            }
+        }
+    }
+
+    fn has_colors(&self) -> bool {
+        self.inner.has_colors()
+    }
+
+    fn screen_size(&self) -> Vec2 {
+        self.inner.screen_size()
+    }
+
+    fn print_at(&self, pos: Vec2, text: &str) {
+        self.inner.print_at(pos, text);
+        let size = self.size.get();
+        let mut screen = self.screen.borrow_mut();
+        let (colors, effects) = (self.colors.get(), self.effects.get());
+        for (i, ch) in text.chars().enumerate() {
+            if pos.x + i < size.x && pos.y < size.y {
+                screen[pos.y * size.x + pos.x + i] = Glyph {
+                    ch,
+                    colors,
+                    effects,
+                };
+            }
+        }
+    }
+
+    fn clear(&self, color: Color) {
+        self.inner.clear(color);
+        self._check_size();
+        let blank = self._blank(ColorPair {
+            front: color,
+            back: color,
+        });
+        self.screen.borrow_mut().fill(blank);
+    }
+
+    fn set_color(&self, colors: ColorPair) -> ColorPair {
+        self.colors.set(colors);
+        self.inner.set_color(colors)
+    }
+
+    fn set_effect(&self, effect: Effect) {
+        self.effects.set(self.effects.get() | _effect_bit(effect));
+        self.inner.set_effect(effect)
+    }
+
+    fn unset_effect(&self, effect: Effect) {
+        self.effects.set(self.effects.get() & !_effect_bit(effect));
+        self.inner.unset_effect(effect)
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+}
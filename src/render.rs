@@ -0,0 +1,475 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::write;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::universe::Universe;
+
+/// Colours are RGB triples
+pub type Colour = [u8; 3];
+
+/// How cells are drawn in images
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+    /// Side of a cell in pixels
+    pub cell_size: u16,
+    pub alive: Colour,
+    pub dead: Colour,
+    /// Colour of the generation counter
+    pub text: Colour,
+    /// Colour of the lines between cells, without lines if None
+    pub grid: Option<Colour>,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            cell_size: 4,
+            alive: [255, 255, 255],
+            dead: [0, 0, 48],
+            text: [255, 208, 64],
+            grid: None,
+        }
+    }
+}
+
+/// Reads "#rrggbb" or "rrggbb"
+pub fn parse_colour(text: &str) -> Option<Colour> {
+    let hex = text.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let mut c = [0u8; 3];
+    for (i, v) in c.iter_mut().enumerate() {
+        *v = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(c)
+}
+
+pub fn format_colour(c: Colour) -> String {
+    format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])
+}
+
+/// Pixel indices of the region (x, y, width, height): 0 for dead cells,
+/// 1 for live ones, in rows of width * cell_size pixels.
+pub fn raster(
+    cells: &HashSet<(i32, i32)>,
+    region: (i32, i32, i32, i32),
+    cell_size: u16,
+) -> Vec<u8> {
+    let (x0, y0, w, h) = region;
+    let cs = cell_size as usize;
+    let pw = w as usize * cs;
+    let mut pixels = vec![0u8; pw * h as usize * cs];
+    for &(x, y) in cells.iter() {
+        if x < x0 || y < y0 || x >= x0 + w || y >= y0 + h {
+            continue;
+        }
+        let (px, py) = ((x - x0) as usize * cs, (y - y0) as usize * cs);
+        for row in py..py + cs {
+            pixels[row * pw + px..row * pw + px + cs].fill(1);
+        }
+    }
+    pixels
+}
+
+/// Palette of the indexed images: dead, alive, counter and grid
+fn _palette(style: &Style) -> Vec<u8> {
+    [
+        style.dead,
+        style.alive,
+        style.text,
+        style.grid.unwrap_or(style.dead),
+    ]
+    .concat()
+}
+
+/// Lines on the top and left edges of the cells with the pixel index
+/// `ink`. Cells smaller than 3 pixels get no grid.
+pub fn draw_grid(pixels: &mut [u8], width: usize, cell_size: u16, ink: u8) {
+    let cs = cell_size as usize;
+    if cs < 3 || width == 0 {
+        return;
+    }
+    for (y, row) in pixels.chunks_mut(width).enumerate() {
+        if y % cs == 0 {
+            row.fill(ink);
+        } else {
+            for x in (0..width).step_by(cs) {
+                row[x] = ink;
+            }
+        }
+    }
+}
+
+/// 3x5 digits, one row of three bits per entry
+const DIGITS: [[u8; 5]; 10] = [
+    [7, 5, 5, 5, 7],
+    [2, 6, 2, 2, 7],
+    [7, 1, 7, 4, 7],
+    [7, 1, 3, 1, 7],
+    [5, 5, 7, 1, 1],
+    [7, 4, 7, 1, 7],
+    [7, 4, 7, 5, 7],
+    [7, 1, 1, 2, 2],
+    [7, 5, 7, 5, 7],
+    [7, 5, 7, 1, 7],
+];
+
+/// Writes the number in the top left corner with the pixel index `ink`,
+/// over a box of dead pixels
+pub fn draw_number(pixels: &mut [u8], width: usize, n: i64, ink: u8, scale: usize) {
+    let text = n.to_string();
+    let height = pixels.len() / width.max(1);
+    let (bw, bh) = ((text.len() * 4 + 1) * scale, 7 * scale);
+    for y in 0..bh.min(height) {
+        pixels[y * width..y * width + bw.min(width)].fill(0);
+    }
+    for (i, c) in text.chars().enumerate() {
+        let glyph = match c.to_digit(10) {
+            Some(d) => DIGITS[d as usize],
+            None => [0, 0, 7, 0, 0],
+        };
+        for (gy, bits) in glyph.iter().enumerate() {
+            for gx in 0..3 {
+                if bits & (4 >> gx) == 0 {
+                    continue;
+                }
+                let (px, py) = ((1 + i * 4 + gx) * scale, (1 + gy) * scale);
+                for y in py..py + scale {
+                    for x in px..px + scale {
+                        if x < width && y < height {
+                            pixels[y * width + x] = ink;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Animation of generations `from..=to`
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub from: i64,
+    pub to: i64,
+    /// Shown region (x, y, width, height), else the bounding box of all
+    /// the frames with a margin of one cell
+    pub region: Option<(i32, i32, i32, i32)>,
+    pub style: Style,
+    /// Between frames, hundredths of a second
+    pub delay: u16,
+    /// Draws the generation number in the corner
+    pub counter: bool,
+}
+
+impl Animation {
+    /// Number of generations `export_gif` computes from the given one,
+    /// twice those shown when the frames have to be sized first
+    pub fn steps(&self, generation: i64) -> usize {
+        let shown = (self.to - self.from).max(0);
+        let sizing = if self.region.is_none() { shown } else { 0 };
+        ((self.from - generation).max(0) + shown + sizing) as usize
+    }
+}
+
+fn _gif_error(e: gif::EncodingError) -> io::Error {
+    match e {
+        gif::EncodingError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+/// Writes the animation of the universe as a looping GIF, returns the
+/// number of frames. `progress` is called once per computed generation.
+/// Frames are encoded as they are computed; without a region, a first
+/// run over the generations finds their bounding box.
+pub fn export_gif(
+    mut universe: Universe,
+    anim: &Animation,
+    path: &Path,
+    progress: &mut dyn FnMut(),
+) -> io::Result<usize> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    if anim.from < universe.generation || anim.to < anim.from {
+        return Err(invalid(format!(
+            "Generations {}..{} are not after the current one, {}",
+            anim.from, anim.to, universe.generation
+        )));
+    }
+    while universe.generation < anim.from {
+        universe.step();
+        progress();
+    }
+    let region = match anim.region {
+        Some(region) => region,
+        None => {
+            let (x, y, w, h) = _frames_bbox(universe.clone(), anim.to, progress);
+            (x - 1, y - 1, w + 2, h + 2)
+        }
+    };
+    let cs = anim.style.cell_size.max(1);
+    let (pw, ph) = _image_size(region, cs)?;
+    if pw > u16::MAX as u32 || ph > u16::MAX as u32 {
+        return Err(invalid(format!(
+            "{}x{} pixels is too large for a GIF",
+            pw, ph
+        )));
+    }
+    let style = &anim.style;
+    let mut encoder =
+        gif::Encoder::new(File::create(path)?, pw as u16, ph as u16, &_palette(style))
+            .map_err(_gif_error)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(_gif_error)?;
+    let mut frames = 0;
+    loop {
+        let mut pixels = raster(&universe.cells, region, cs);
+        if style.grid.is_some() {
+            draw_grid(&mut pixels, pw as usize, cs, 3);
+        }
+        if anim.counter {
+            let scale = (cs as usize / 4).max(1);
+            draw_number(&mut pixels, pw as usize, universe.generation, 2, scale);
+        }
+        let frame = gif::Frame {
+            width: pw as u16,
+            height: ph as u16,
+            delay: anim.delay,
+            buffer: Cow::Owned(pixels),
+            ..Default::default()
+        };
+        encoder.write_frame(&frame).map_err(_gif_error)?;
+        frames += 1;
+        if universe.generation >= anim.to {
+            return Ok(frames);
+        }
+        universe.step();
+        progress();
+    }
+}
+
+/// Bounding box of the cells over the generations up to `to`
+fn _frames_bbox(
+    mut universe: Universe,
+    to: i64,
+    progress: &mut dyn FnMut(),
+) -> (i32, i32, i32, i32) {
+    let mut bbox: Option<(i32, i32, i32, i32)> = None;
+    loop {
+        if !universe.cells.is_empty() {
+            let (x, y, w, h) = universe.bbox();
+            let (x1, y1) = (x + w, y + h);
+            bbox = Some(match bbox {
+                None => (x, y, x1, y1),
+                Some((bx, by, bx1, by1)) => (bx.min(x), by.min(y), bx1.max(x1), by1.max(y1)),
+            });
+        }
+        if universe.generation >= to {
+            break;
+        }
+        universe.step();
+        progress();
+    }
+    bbox.map_or((0, 0, 0, 0), |(x, y, x1, y1)| (x, y, x1 - x, y1 - y))
+}
+
+/// Largest snapshot, in pixels
+const MAX_PIXELS: i64 = 1 << 28;
+
+fn _image_size(region: (i32, i32, i32, i32), cell_size: u16) -> io::Result<(u32, u32)> {
+    let (pw, ph) = (
+        region.2 as i64 * cell_size as i64,
+        region.3 as i64 * cell_size as i64,
+    );
+    if pw <= 0 || ph <= 0 || pw * ph > MAX_PIXELS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Cannot make a {}x{} pixels image", pw, ph),
+        ));
+    }
+    Ok((pw as u32, ph as u32))
+}
+
+/// Indexed PNG of the cells in the region (x, y, width, height)
+pub fn write_png(
+    cells: &HashSet<(i32, i32)>,
+    region: (i32, i32, i32, i32),
+    style: &Style,
+    path: &Path,
+) -> io::Result<()> {
+    let cs = style.cell_size.max(1);
+    let (pw, ph) = _image_size(region, cs)?;
+    let mut pixels = raster(cells, region, cs);
+    if style.grid.is_some() {
+        draw_grid(&mut pixels, pw as usize, cs, 3);
+    }
+    let mut encoder = png::Encoder::new(io::BufWriter::new(File::create(path)?), pw, ph);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(_palette(style));
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&pixels).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// SVG of the cells in the region, live cells of a row merged into runs
+pub fn svg(cells: &HashSet<(i32, i32)>, region: (i32, i32, i32, i32), style: &Style) -> String {
+    let (x0, y0, w, h) = region;
+    let cs = style.cell_size.max(1) as i64;
+    let (pw, ph) = (w as i64 * cs, h as i64 * cs);
+    let mut s = String::new();
+    write(
+        &mut s,
+        format_args!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{pw}\" height=\"{ph}\" \
+             viewBox=\"0 0 {pw} {ph}\">\n\
+             <rect width=\"{pw}\" height=\"{ph}\" fill=\"{}\"/>\n\
+             <g fill=\"{}\">\n",
+            format_colour(style.dead),
+            format_colour(style.alive)
+        ),
+    )
+    .unwrap();
+    let mut rows: Vec<(i32, i32)> = cells
+        .iter()
+        .filter(|&&(x, y)| x >= x0 && y >= y0 && x < x0 + w && y < y0 + h)
+        .map(|&(x, y)| (y, x))
+        .collect();
+    rows.sort_unstable();
+    let mut i = 0;
+    while i < rows.len() {
+        let (y, x) = rows[i];
+        let mut n = 1;
+        while i + n < rows.len() && rows[i + n] == (y, x + n as i32) {
+            n += 1;
+        }
+        write(
+            &mut s,
+            format_args!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>\n",
+                (x - x0) as i64 * cs,
+                (y - y0) as i64 * cs,
+                n as i64 * cs,
+                cs
+            ),
+        )
+        .unwrap();
+        i += n;
+    }
+    s.push_str("</g>\n");
+    if let Some(grid) = style.grid {
+        let mut d = String::new();
+        for y in 0..=h as i64 {
+            write(&mut d, format_args!("M0 {}H{}", y * cs, pw)).unwrap();
+        }
+        for x in 0..=w as i64 {
+            write(&mut d, format_args!("M{} 0V{}", x * cs, ph)).unwrap();
+        }
+        write(
+            &mut s,
+            format_args!(
+                "<path d=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+                d,
+                format_colour(grid),
+                (cs as f64 / 8.0).max(0.5)
+            ),
+        )
+        .unwrap();
+    }
+    s.push_str("</svg>\n");
+    s
+}
+
+/// Writes the snapshot as SVG for a .svg path, as PNG otherwise
+pub fn export_image(
+    cells: &HashSet<(i32, i32)>,
+    region: (i32, i32, i32, i32),
+    style: &Style,
+    path: &Path,
+) -> io::Result<()> {
+    match path.extension() {
+        Some(e) if e.eq_ignore_ascii_case("svg") => {
+            _image_size(region, style.cell_size.max(1))?;
+            std::fs::write(path, svg(cells, region, style))
+        }
+        _ => write_png(cells, region, style, path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule;
+
+    #[test]
+    fn test_export_gif() {
+        assert_eq!(parse_colour("#10ff0A"), Some([16, 255, 10]));
+        assert_eq!(parse_colour("fff"), None);
+        let blinker = Universe::with_cells(rule::LIFE, [(0, 0), (1, 0), (2, 0)]);
+        let anim = Animation {
+            from: 1,
+            to: 4,
+            region: None,
+            style: Style::default(),
+            delay: 10,
+            counter: true,
+        };
+        let path = std::env::temp_dir().join(format!("lf-anim-{}.gif", std::process::id()));
+        let mut steps = 0;
+        assert_eq!(
+            export_gif(blinker, &anim, &path, &mut || steps += 1).unwrap(),
+            4
+        );
+        assert_eq!(steps, 7);
+        assert_eq!(anim.steps(0), 7);
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(File::open(&path).unwrap())
+            .unwrap();
+        // 3x3 cells of the blinker with the margin
+        assert_eq!((decoder.width(), decoder.height()), (20, 20));
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.delay, 10);
+            frames += 1;
+        }
+        assert_eq!(frames, 4);
+        let too_large = Animation {
+            region: Some((0, 0, 100_000, 100_000)),
+            ..anim
+        };
+        let blinker = Universe::with_cells(rule::LIFE, [(0, 0), (1, 0), (2, 0)]);
+        let err = export_gif(blinker, &too_large, &path, &mut || ()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let cells: HashSet<(i32, i32)> = [(0, 0), (1, 0), (2, 0), (1, 2)].into_iter().collect();
+        let style = Style {
+            cell_size: 8,
+            grid: Some([1, 2, 3]),
+            ..Default::default()
+        };
+        let text = svg(&cells, (-1, -1, 5, 5), &style);
+        // The row of three is one rectangle
+        assert_eq!(text.matches("<rect").count(), 3);
+        assert!(text.contains("<rect x=\"8\" y=\"8\" width=\"24\" height=\"8\"/>"));
+        assert!(text.contains("stroke=\"#010203\""));
+        let path = std::env::temp_dir().join(format!("lf-snapshot-{}.png", std::process::id()));
+        export_image(&cells, (0, 0, 3, 3), &style, &path).unwrap();
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        assert_eq!((info.width, info.height), (24, 24));
+        // Inside of the first cell is alive, its corner is on the grid
+        assert_eq!((buf[3 * 24 + 3], buf[9 * 24 + 9], buf[0]), (1, 0, 3));
+        assert!(export_image(&cells, (0, 0, 1 << 20, 1 << 20), &style, &path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
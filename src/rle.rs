@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::write;
+
+/// Longest run accepted when reading, files beyond it are refused
+pub const MAX_RUN: i32 = 1_000_000;
+
+/// Most live cells accepted when reading
+pub const MAX_CELLS: usize = 20_000_000;
+
+/// Reads the cells of a pattern in run length encoded format. Header and
+/// comment lines are skipped, all states other than dead are read as alive.
+pub fn parse(text: &str) -> Result<Vec<(i32, i32)>, String> {
+    let too_long = || format!("Run longer than {} in RLE", MAX_RUN);
+    let too_far = || String::from("Pattern too large in RLE");
+    let mut cells: Vec<(i32, i32)> = vec![];
+    let (mut x, mut y) = (0i32, 0i32);
+    let mut count: i32 = 0;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('#') || line.starts_with("x ") || line.starts_with("x=") {
+            continue;
+        }
+        for ch in line.chars() {
+            if let Some(d) = ch.to_digit(10) {
+                count = count
+                    .checked_mul(10)
+                    .and_then(|c| c.checked_add(d as i32))
+                    .filter(|c| *c <= MAX_RUN)
+                    .ok_or_else(too_long)?;
+                continue;
+            }
+            let n = count.max(1);
+            count = 0;
+            match ch {
+                'b' | '.' => x = x.checked_add(n).ok_or_else(too_far)?,
+                '$' => {
+                    y = y.checked_add(n).ok_or_else(too_far)?;
+                    x = 0;
+                }
+                '!' => return Ok(cells),
+                c if c.is_whitespace() => {}
+                c if c.is_ascii_alphabetic() => {
+                    let end = x.checked_add(n).ok_or_else(too_far)?;
+                    if cells.len() + n as usize > MAX_CELLS {
+                        return Err(format!("More than {} live cells in RLE", MAX_CELLS));
+                    }
+                    cells.extend((x..end).map(|cx| (cx, y)));
+                    x = end;
+                }
+                c => return Err(format!("Unexpected character '{}' in RLE", c)),
+            }
+        }
+    }
+    Err(String::from("RLE ended without '!'"))
+}
+
+/// Writes `n` times the tag, split in runs no longer than the reader
+/// accepts
+fn _push_run(s: &mut String, line_len: &mut usize, mut n: i64, tag: char) {
+    while n > 0 {
+        let len = n.min(MAX_RUN as i64);
+        n -= len;
+        let mut run = String::new();
+        if len > 1 {
+            write(&mut run, format_args!("{}", len)).unwrap();
+        }
+        run.push(tag);
+        // Lines of RLE files should not exceed 70 characters
+        if *line_len + run.len() > 70 {
+            s.push('\n');
+            *line_len = 0;
+        }
+        *line_len += run.len();
+        s.push_str(&run);
+    }
+}
+
+fn _header(s: &mut String, w: i64, h: i64, rule: &str) {
+    write(s, format_args!("x = {}, y = {}", w, h)).unwrap();
+    if !rule.is_empty() {
+        write(s, format_args!(", rule = {}", rule)).unwrap();
+    }
+    s.push('\n');
+}
+
+/// Writes the cells as RLE, translated so that the bounding box starts at
+/// the origin. An empty rule is left out of the header.
+pub fn write_rle(cells: &HashSet<(i32, i32)>, rule: &str) -> String {
+    let mut s = String::new();
+    if cells.is_empty() {
+        _header(&mut s, 0, 0, rule);
+        s.push_str("!\n");
+        return s;
+    }
+    let x0 = cells.iter().map(|c| c.0).min().unwrap() as i64;
+    let x1 = cells.iter().map(|c| c.0).max().unwrap() as i64;
+    let y0 = cells.iter().map(|c| c.1).min().unwrap() as i64;
+    let y1 = cells.iter().map(|c| c.1).max().unwrap() as i64;
+    _header(&mut s, x1 - x0 + 1, y1 - y0 + 1, rule);
+    // Only the rows with cells, the pattern may be sparse
+    let mut rows: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+    for (x, y) in cells.iter() {
+        rows.entry(*y as i64 - y0).or_default().push(*x as i64 - x0);
+    }
+    let mut line_len = 0;
+    let mut last_y = 0;
+    for (y, row) in rows.iter_mut() {
+        _push_run(&mut s, &mut line_len, y - last_y, '$');
+        last_y = *y;
+        row.sort_unstable();
+        let mut x = 0;
+        let mut k = 0;
+        while k < row.len() {
+            let mut run = 1;
+            while k + run < row.len() && row[k + run] == row[k] + run as i64 {
+                run += 1;
+            }
+            _push_run(&mut s, &mut line_len, row[k] - x, 'b');
+            _push_run(&mut s, &mut line_len, run as i64, 'o');
+            x = row[k] + run as i64;
+            k += run;
+        }
+    }
+    s.push_str("!\n");
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rle_parse() {
+        let glider = parse("#N Glider\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!").unwrap();
+        assert_eq!(glider, vec![(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)]);
+        assert_eq!(parse("o3bo3$3o!").unwrap().len(), 5);
+        assert!(parse("2o$2o").is_err());
+        assert!(parse("2o$2o%!").is_err());
+        // Untrusted files: no overflow, no huge allocation
+        assert!(parse("99999999999999999999o!").is_err());
+        assert!(parse("1000001o!").is_err());
+        assert!(parse(&format!("{}o!", "1000000b".repeat(2200))).is_err());
+        assert!(parse(&format!("{}o!", "1000000$".repeat(2200))).is_err());
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        let cells: HashSet<(i32, i32)> = [(10, 10), (14, 10), (10, 13), (11, 13), (12, 13)]
+            .into_iter()
+            .collect();
+        let text = write_rle(&cells, "B3/S23");
+        assert_eq!(text, "x = 5, y = 4, rule = B3/S23\no3bo3$3o!\n");
+        let back: HashSet<(i32, i32)> = parse(&text)
+            .unwrap()
+            .into_iter()
+            .map(|(x, y)| (x + 10, y + 10))
+            .collect();
+        assert_eq!(back, cells);
+        assert_eq!(write_rle(&HashSet::new(), ""), "x = 0, y = 0\n!\n");
+        // Gaps longer than the reader accepts are split
+        let far: HashSet<(i32, i32)> = [(0, 0), (2_500_000, 0), (1, 2_000_001)]
+            .into_iter()
+            .collect();
+        let text = write_rle(&far, "");
+        assert!(text.contains("1000000b1000000b499999bo"));
+        let back: HashSet<(i32, i32)> = parse(&text).unwrap().into_iter().collect();
+        assert_eq!(back, far);
+    }
+}
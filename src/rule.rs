@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Outer totalistic rule: the neighbour counts for which a dead cell is
+/// born and a live one survives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub birth: [bool; 9],
+    pub survival: [bool; 9],
+}
+
+pub const LIFE: Rule = Rule {
+    birth: [false, false, false, true, false, false, false, false, false],
+    survival: [false, false, true, true, false, false, false, false, false],
+};
+
+fn _counts(digits: &str, rule: &str) -> Result<[bool; 9], String> {
+    let mut res = [false; 9];
+    for c in digits.chars() {
+        match c.to_digit(10) {
+            Some(n) if n < 9 => res[n as usize] = true,
+            _ => return Err(format!("Bad rule '{}'", rule)),
+        }
+    }
+    Ok(res)
+}
+
+impl Rule {
+    /// Reads "B3/S23", in any case, or the older survival/birth "23/3"
+    pub fn parse(text: &str) -> Result<Rule, String> {
+        let text = text.trim();
+        let (left, right) = text
+            .split_once('/')
+            .ok_or_else(|| format!("Bad rule '{}'", text))?;
+        let strip = |s: &str, p: char| {
+            s.strip_prefix([p, p.to_ascii_lowercase()])
+                .map(String::from)
+        };
+        let (b, s) = match (strip(left, 'B'), strip(right, 'S')) {
+            (Some(b), Some(s)) => (b, s),
+            (None, None) => (right.to_string(), left.to_string()),
+            _ => return Err(format!("Bad rule '{}'", text)),
+        };
+        let rule = Rule {
+            birth: _counts(&b, text)?,
+            survival: _counts(&s, text)?,
+        };
+        if rule.birth[0] {
+            return Err(String::from("B0 rules are not supported"));
+        }
+        Ok(rule)
+    }
+
+    /// Next generation
+    pub fn step(&self, f: &mut HashSet<(i32, i32)>) {
+        self.step_counted(f);
+    }
+
+    /// Next generation, returns the numbers of cells born and dead
+    pub fn step_counted(&self, f: &mut HashSet<(i32, i32)>) -> (usize, usize) {
+        let mut nc: HashMap<(i32, i32), usize> = HashMap::new();
+        for (cx, cy) in f.iter() {
+            for dx in -1..2 {
+                for dy in -1..2 {
+                    if dx != 0 || dy != 0 {
+                        *nc.entry((cx + dx, cy + dy)).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        let born: Vec<(i32, i32)> = nc
+            .iter()
+            .filter(|&(c, &n)| self.birth[n] && !f.contains(c))
+            .map(|(c, _)| *c)
+            .collect();
+        let died: Vec<(i32, i32)> = f
+            .iter()
+            .filter(|&c| !self.survival[nc.get(c).copied().unwrap_or(0)])
+            .cloned()
+            .collect();
+        let counts = (born.len(), died.len());
+        for d in died {
+            f.remove(&d);
+        }
+        for b in born {
+            f.insert(b);
+        }
+        counts
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = |counts: &[bool; 9]| -> String {
+            (0..9)
+                .filter(|&n| counts[n])
+                .map(|n| char::from(b'0' + n as u8))
+                .collect()
+        };
+        write!(f, "B{}/S{}", digits(&self.birth), digits(&self.survival))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule() {
+        assert_eq!(Rule::parse("B3/S23"), Ok(LIFE));
+        assert_eq!(Rule::parse("23/3"), Ok(LIFE));
+        let highlife = Rule::parse("b36/s23").unwrap();
+        assert_eq!(highlife.to_string(), "B36/S23");
+        assert!(Rule::parse("B3").is_err());
+        assert!(Rule::parse("B39/S23").is_err());
+        assert!(Rule::parse("B03/S23").is_err());
+        let mut f: HashSet<(i32, i32)> = [(0, 0), (1, 0), (2, 0)].into_iter().collect();
+        LIFE.step(&mut f);
+        assert_eq!(f, [(1, -1), (1, 0), (1, 1)].into_iter().collect());
+        // Without survival the blinker dies
+        Rule::parse("B3/S").unwrap().step(&mut f);
+        assert_eq!(f, [(0, 0), (2, 0)].into_iter().collect());
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use crate::patterns;
+
+/// Where a query was found: top left corner of its bounding box, the
+/// orientation (see patterns::ORIENTATIONS) and the generation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Match {
+    pub x: i32,
+    pub y: i32,
+    pub orientation: usize,
+    pub generation: i64,
+}
+
+/// Occurrences of the query in the cells. The query is looked for as an
+/// isolated object: its bounding box has to match exactly and be surrounded
+/// by dead cells, so that a block does not match inside of a dense soup.
+pub fn find(cells: &HashSet<(i32, i32)>, query: &[(i32, i32)], oriented: bool) -> Vec<Match> {
+    let mut res: Vec<Match> = vec![];
+    if query.is_empty() {
+        return res;
+    }
+    let mut seen: Vec<Vec<(i32, i32)>> = vec![];
+    for orientation in 0..if oriented { 8 } else { 1 } {
+        let mut q = patterns::orient(query, orientation);
+        q.sort_unstable();
+        // Symmetric queries have identical orientations
+        if seen.contains(&q) {
+            continue;
+        }
+        let w = q.iter().map(|c| c.0).max().unwrap() + 1;
+        let h = q.iter().map(|c| c.1).max().unwrap() + 1;
+        let qset: HashSet<(i32, i32)> = q.iter().cloned().collect();
+        // Every match puts the first query cell on a live cell
+        let (ax, ay) = q[0];
+        for &(cx, cy) in cells.iter() {
+            let (ox, oy) = (cx - ax, cy - ay);
+            let mut ok = true;
+            'outer: for y in -1..=h {
+                for x in -1..=w {
+                    if cells.contains(&(ox + x, oy + y)) != qset.contains(&(x, y)) {
+                        ok = false;
+                        break 'outer;
+                    }
+                }
+            }
+            if ok {
+                res.push(Match {
+                    x: ox,
+                    y: oy,
+                    orientation,
+                    generation: 0,
+                });
+            }
+        }
+        seen.push(q);
+    }
+    res.sort_by_key(|m| (m.y, m.x, m.orientation));
+    res
+}
+
+/// Advances the cells by a generation
+pub type Step<'a> = dyn Fn(&mut HashSet<(i32, i32)>) + 'a;
+
+/// First generation, up to `generations`, in which the query appears.
+pub fn find_evolving(
+    cells: &HashSet<(i32, i32)>,
+    query: &[(i32, i32)],
+    oriented: bool,
+    generations: i64,
+    step: &Step<'_>,
+) -> Vec<Match> {
+    let mut f = cells.clone();
+    for gen in 0..=generations {
+        let found = find(&f, query, oriented);
+        if !found.is_empty() {
+            return found
+                .into_iter()
+                .map(|m| Match {
+                    generation: gen,
+                    ..m
+                })
+                .collect();
+        }
+        if gen < generations {
+            step(&mut f);
+        }
+    }
+    vec![]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let mut cells: HashSet<(i32, i32)> = HashSet::new();
+        for (x, y) in patterns::get("Glider").unwrap() {
+            cells.insert((x + 10, y + 20));
+        }
+        for (x, y) in patterns::orient(&patterns::get("Glider").unwrap(), 1) {
+            cells.insert((x - 10, y));
+        }
+        let glider = patterns::get("Glider").unwrap();
+        let found = find(&cells, &glider, false);
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].x, found[0].y), (10, 20));
+        let found = find(&cells, &glider, true);
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].x, found[0].y, found[0].orientation), (-10, 0, 1));
+        // Part of a larger object does not match
+        assert!(find(&cells, &[(0, 0), (1, 0)], true).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::symmetry::Symmetry;
+
+/// SplitMix64: tiny, fast and, above all, stable across versions, so a seed
+/// always reproduces the same soup.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub fn time_seed() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_nanos() as u64,
+        Err(_) => 0,
+    }
+}
+
+/// Largest width or height of a soup
+pub const MAX_SIDE: i32 = 4096;
+
+/// Largest distance of a soup corner from the origin on either axis, which
+/// keeps the doubled coordinates of the symmetry centre in range
+pub const MAX_COORD: i32 = 1 << 28;
+
+/// Parameters of a random fill; enough to regenerate it exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Soup {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    /// Probability of a cell being alive, 0.0 - 1.0
+    pub density: f64,
+    pub symmetry: Symmetry,
+    pub seed: u64,
+}
+
+impl Soup {
+    /// Whether the parameters can be generated: typed in, or read from a
+    /// file that may be anything.
+    pub fn check(&self) -> Result<(), String> {
+        if !(1..=MAX_SIDE).contains(&self.width) || !(1..=MAX_SIDE).contains(&self.height) {
+            return Err(format!(
+                "Width and height must be between 1 and {}",
+                MAX_SIDE
+            ));
+        }
+        if self.x.abs() > MAX_COORD || self.y.abs() > MAX_COORD {
+            return Err(format!("X and Y must be between -{0} and {0}", MAX_COORD));
+        }
+        if !(0.0..=1.0).contains(&self.density) {
+            return Err(String::from("Density must be between 0 and 100"));
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        let (x, y) = (x as i64, y as i64);
+        let (x0, y0) = (self.x as i64, self.y as i64);
+        x >= x0 && y >= y0 && x < x0 + self.width as i64 && y < y0 + self.height as i64
+    }
+
+    /// Live cells of the soup. Only one representative of every orbit of the
+    /// symmetry group is drawn from the generator, so the density holds for
+    /// every symmetry. Empty if the parameters do not pass `check`.
+    pub fn generate(&self) -> HashSet<(i32, i32)> {
+        let mut rng = Rng::new(self.seed);
+        let mut cells: HashSet<(i32, i32)> = HashSet::new();
+        if self.check().is_err() {
+            return cells;
+        }
+        let centre2 = (2 * self.x + self.width - 1, 2 * self.y + self.height - 1);
+        for y in self.y..self.y + self.height {
+            for x in self.x..self.x + self.width {
+                let orbit = self.symmetry.images(centre2, (x, y));
+                if orbit
+                    .iter()
+                    .any(|&(ox, oy)| (oy, ox) < (y, x) && self.contains(ox, oy))
+                {
+                    continue;
+                }
+                if rng.next_f64() < self.density {
+                    for (ox, oy) in orbit {
+                        if self.contains(ox, oy) {
+                            cells.insert((ox, oy));
+                        }
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    /// Replaces the contents of the soup's rectangle in the field.
+    pub fn fill(&self, field: &mut HashSet<(i32, i32)>) {
+        field.retain(|&(x, y)| !self.contains(x, y));
+        field.extend(self.generate());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn soup(symmetry: Symmetry, seed: u64) -> Soup {
+        Soup {
+            x: -3,
+            y: 5,
+            width: 16,
+            height: 16,
+            density: 0.5,
+            symmetry,
+            seed,
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_soup() {
+        assert_eq!(
+            soup(Symmetry::C1, 42).generate(),
+            soup(Symmetry::C1, 42).generate()
+        );
+        assert_ne!(
+            soup(Symmetry::C1, 42).generate(),
+            soup(Symmetry::C1, 43).generate()
+        );
+    }
+
+    #[test]
+    fn test_soup_symmetry() {
+        let s = soup(Symmetry::D8, 7);
+        let cells = s.generate();
+        assert!(!cells.is_empty());
+        for &(x, y) in cells.iter() {
+            assert!(s.contains(x, y));
+            // Mirror around the vertical axis and the diagonal
+            assert!(cells.contains(&(2 * s.x + s.width - 1 - x, y)));
+            assert!(cells.contains(&(y - s.y + s.x, x - s.x + s.y)));
+        }
+    }
+
+    #[test]
+    fn test_soup_bounds() {
+        let mut s = soup(Symmetry::C1, 1);
+        s.x = i32::MAX - 4;
+        assert!(s.check().is_err());
+        assert!(s.generate().is_empty());
+        // No overflow past the end of the range
+        assert!(s.contains(i32::MAX, 5));
+        let mut s = soup(Symmetry::C1, 1);
+        s.width = MAX_SIDE + 1;
+        assert!(s.check().is_err());
+        assert!(soup(Symmetry::C4, 1).check().is_ok());
+    }
+}
@@ -0,0 +1,768 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::db::{self, Error, RecordMeta, Result, Revision, Storage};
+use crate::patterns;
+use crate::rle;
+use crate::search;
+use crate::soup::Soup;
+use crate::symmetry::Symmetry;
+use crate::universe;
+
+/// A collection of named patterns with their metadata. Only the SQLite
+/// storage keeps revisions and indexes the patterns; the provided methods
+/// do without, scanning the records when needed.
+pub trait PatternStore {
+    /// Names of the records, sorted
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// Saves a new record. Population, bounding box and timestamps of the
+    /// metadata are computed here.
+    fn save(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+    ) -> Result<()>;
+
+    /// Replaces the contents of an existing record, keeping its creation time.
+    fn overwrite(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+    ) -> Result<()>;
+
+    /// Saves a record coming from another archive, keeping the timestamps of
+    /// its metadata. With `replace`, over the existing record of that name.
+    fn import_record(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+        replace: bool,
+    ) -> Result<()>;
+
+    /// Runs `f` on the store, keeping either all the changes it makes or,
+    /// when it fails, none.
+    fn atomically(&mut self, f: &mut dyn FnMut(&mut dyn PatternStore) -> Result<()>) -> Result<()>;
+
+    fn load(&self, name: &str) -> Result<Vec<(i32, i32)>>;
+
+    fn load_meta(&self, name: &str) -> Result<RecordMeta>;
+
+    /// Parameters of the soup the record was generated from, if any.
+    fn load_soup(&self, name: &str) -> Result<Option<Soup>>;
+
+    fn delete(&mut self, name: &str) -> Result<()>;
+
+    fn rename(&mut self, name: &str, new_name: &str) -> Result<()>;
+
+    fn exists(&self, name: &str) -> Result<bool> {
+        Ok(self.list()?.iter().any(|n| n == name))
+    }
+
+    /// Copies the record with its cells, soup and metadata under a new name.
+    fn duplicate(&mut self, name: &str, new_name: &str) -> Result<()> {
+        let cells: HashSet<(i32, i32)> = self.load(name)?.into_iter().collect();
+        let soup = self.load_soup(name)?;
+        let meta = self.load_meta(name)?;
+        self.save(new_name, &cells, soup.as_ref(), &meta)
+    }
+
+    /// All the records with their metadata
+    fn records_meta(&self) -> Result<Vec<(String, RecordMeta)>> {
+        let mut res: Vec<(String, RecordMeta)> = vec![];
+        for name in self.list()? {
+            let meta = self.load_meta(&name)?;
+            res.push((name, meta));
+        }
+        Ok(res)
+    }
+
+    /// Names of the records holding the same pattern, up to translation, and
+    /// with `oriented` also up to rotation and reflection.
+    fn find_equivalent(&self, cells: &HashSet<(i32, i32)>, oriented: bool) -> Result<Vec<String>> {
+        let list: Vec<(i32, i32)> = cells.iter().cloned().collect();
+        let mut res: Vec<String> = vec![];
+        if list.is_empty() {
+            return Ok(res);
+        }
+        let canonical = patterns::canonical(&list, oriented);
+        for name in self.list()? {
+            if patterns::canonical(&self.load(&name)?, oriented) == canonical {
+                res.push(name);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Groups of names of the records holding the same non-empty pattern
+    fn duplicates(&self, oriented: bool) -> Result<Vec<Vec<String>>> {
+        let mut records: Vec<(String, Vec<(i32, i32)>)> = vec![];
+        for name in self.list()? {
+            let cells = self.load(&name)?;
+            if !cells.is_empty() {
+                records.push((name, cells));
+            }
+        }
+        Ok(patterns::group_equivalent(records, oriented))
+    }
+
+    /// Records containing the query, with `oriented` in any orientation. With
+    /// `generations` > 0 the records are also evolved using `step`, and the
+    /// first generation in which the query appears is reported.
+    fn search(
+        &self,
+        query: &[(i32, i32)],
+        oriented: bool,
+        generations: i64,
+        step: &search::Step<'_>,
+    ) -> Result<Vec<(String, Vec<search::Match>)>> {
+        let mut res: Vec<(String, Vec<search::Match>)> = vec![];
+        for name in self.list()? {
+            let cells: HashSet<(i32, i32)> = self.load(&name)?.into_iter().collect();
+            let found = search::find_evolving(&cells, query, oriented, generations, step);
+            if !found.is_empty() {
+                res.push((name, found));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Whether the store keeps the previous revisions of the records. The
+    /// revision methods of those that do not fail with Error::Unsupported.
+    fn has_revisions(&self) -> bool {
+        false
+    }
+
+    /// Previous revisions of the record followed by the current one
+    fn revisions(&self, _name: &str) -> Result<Vec<Revision>> {
+        Err(Error::Unsupported("revisions"))
+    }
+
+    fn load_revision(&self, _name: &str, _number: i64) -> Result<Vec<(i32, i32)>> {
+        Err(Error::Unsupported("revisions"))
+    }
+
+    /// Soup and metadata of the record as of the revision
+    fn load_revision_meta(&self, _name: &str, _number: i64) -> Result<(Option<Soup>, RecordMeta)> {
+        Err(Error::Unsupported("revisions"))
+    }
+
+    /// Makes an old revision the current one
+    fn restore_revision(&mut self, _name: &str, _number: i64) -> Result<()> {
+        Err(Error::Unsupported("revisions"))
+    }
+}
+
+/// Cells, soup and metadata of a record
+pub type Record = (Vec<(i32, i32)>, Option<Soup>, RecordMeta);
+
+/// Creation and modification times of an imported record, now for those
+/// it does not have
+pub fn kept_times(meta: &RecordMeta) -> (i64, i64) {
+    let created = if meta.created > 0 {
+        meta.created
+    } else {
+        db::now()
+    };
+    (created, meta.modified.max(created))
+}
+
+/// Metadata as saved: population and bounding box taken from the cells
+fn _stamp_meta(
+    cells: &HashSet<(i32, i32)>,
+    meta: &RecordMeta,
+    created: i64,
+    modified: i64,
+) -> RecordMeta {
+    RecordMeta {
+        population: cells.len() as i64,
+        bbox: universe::bbox(cells),
+        created,
+        modified,
+        ..meta.clone()
+    }
+}
+
+/// Records kept in memory only, for tests and scratch work
+#[derive(Default)]
+pub struct MemoryStore {
+    records: HashMap<String, Record>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn get(&self, name: &str) -> Result<&Record> {
+        self.records
+            .get(name)
+            .ok_or_else(|| Error::NotFound(name.to_string()))
+    }
+}
+
+impl PatternStore for MemoryStore {
+    fn list(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self.records.keys().cloned().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn save(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+    ) -> Result<()> {
+        if self.records.contains_key(name) {
+            return Err(Error::DuplicateName(name.to_string()));
+        }
+        let ts = db::now();
+        let meta = _stamp_meta(cells, meta, ts, ts);
+        self.records.insert(
+            name.to_string(),
+            (cells.iter().cloned().collect(), soup.cloned(), meta),
+        );
+        Ok(())
+    }
+
+    fn overwrite(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+    ) -> Result<()> {
+        let created = self.get(name)?.2.created;
+        let meta = _stamp_meta(cells, meta, created, db::now());
+        self.records.insert(
+            name.to_string(),
+            (cells.iter().cloned().collect(), soup.cloned(), meta),
+        );
+        Ok(())
+    }
+
+    fn import_record(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+        replace: bool,
+    ) -> Result<()> {
+        match (replace, self.records.contains_key(name)) {
+            (true, false) => return Err(Error::NotFound(name.to_string())),
+            (false, true) => return Err(Error::DuplicateName(name.to_string())),
+            _ => {}
+        }
+        let (created, modified) = kept_times(meta);
+        let meta = _stamp_meta(cells, meta, created, modified);
+        self.records.insert(
+            name.to_string(),
+            (cells.iter().cloned().collect(), soup.cloned(), meta),
+        );
+        Ok(())
+    }
+
+    fn atomically(&mut self, f: &mut dyn FnMut(&mut dyn PatternStore) -> Result<()>) -> Result<()> {
+        let before = self.records.clone();
+        let res = f(self);
+        if res.is_err() {
+            self.records = before;
+        }
+        res
+    }
+
+    fn load(&self, name: &str) -> Result<Vec<(i32, i32)>> {
+        Ok(self.get(name)?.0.clone())
+    }
+
+    fn load_meta(&self, name: &str) -> Result<RecordMeta> {
+        Ok(self.get(name)?.2.clone())
+    }
+
+    fn load_soup(&self, name: &str) -> Result<Option<Soup>> {
+        Ok(self.get(name)?.1.clone())
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        self.records
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| Error::NotFound(name.to_string()))
+    }
+
+    fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.get(name)?;
+        if self.records.contains_key(new_name) {
+            return Err(Error::DuplicateName(new_name.to_string()));
+        }
+        let (cells, soup, mut meta) = self.records.remove(name).unwrap();
+        meta.modified = db::now();
+        self.records
+            .insert(new_name.to_string(), (cells, soup, meta));
+        Ok(())
+    }
+}
+
+/// Records as RLE files in a directory, one per record, so that an archive
+/// can be kept in version control. The metadata goes into the comments:
+/// the standard #N, #O and #C lines, the position and generation as in
+/// Golly's #CXRLE line, and the rest in #CLC lines.
+pub struct DirStore {
+    dir: PathBuf,
+}
+
+const RLE_EXT: &str = "rle";
+
+/// File name of the record: characters not safe in file names are written
+/// as %XX.
+pub fn file_name(name: &str) -> String {
+    let mut s = String::new();
+    for (i, ch) in name.chars().enumerate() {
+        if ch.is_alphanumeric() || "-_ ".contains(ch) || (ch == '.' && i > 0) {
+            s.push(ch);
+        } else {
+            let mut buf = [0u8; 4];
+            for b in ch.encode_utf8(&mut buf).bytes() {
+                write(&mut s, format_args!("%{:02X}", b)).unwrap();
+            }
+        }
+    }
+    s.push('.');
+    s.push_str(RLE_EXT);
+    s
+}
+
+pub fn record_name(stem: &str) -> Option<String> {
+    let mut bytes: Vec<u8> = vec![];
+    let mut it = stem.bytes();
+    while let Some(b) = it.next() {
+        if b == b'%' {
+            let hex: Vec<u8> = it.by_ref().take(2).collect();
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// The RLE file of a record
+pub fn write_rle_file(
+    name: &str,
+    cells: &HashSet<(i32, i32)>,
+    soup: Option<&Soup>,
+    meta: &RecordMeta,
+) -> String {
+    let mut s = String::new();
+    write(&mut s, format_args!("#N {}\n", name)).unwrap();
+    if !meta.author.is_empty() {
+        write(&mut s, format_args!("#O {}\n", meta.author)).unwrap();
+    }
+    for line in meta.description.lines() {
+        write(&mut s, format_args!("#C {}\n", line)).unwrap();
+    }
+    let (x0, y0, _, _) = meta.bbox;
+    write(
+        &mut s,
+        format_args!("#CXRLE Pos={},{} Gen={}\n", x0, y0, meta.generation),
+    )
+    .unwrap();
+    if !meta.tags.is_empty() {
+        write(&mut s, format_args!("#CLC tags {}\n", meta.tags.join(","))).unwrap();
+    }
+    write(
+        &mut s,
+        format_args!(
+            "#CLC created {}\n#CLC modified {}\n",
+            meta.created, meta.modified
+        ),
+    )
+    .unwrap();
+    if let Some(sp) = soup {
+        write(
+            &mut s,
+            format_args!(
+                "#CLC soup {} {} {} {} {} {} {}\n",
+                sp.x, sp.y, sp.width, sp.height, sp.density, sp.symmetry, sp.seed
+            ),
+        )
+        .unwrap();
+    }
+    s.push_str(&rle::write_rle(cells, &meta.rule));
+    s
+}
+
+pub fn read_rle_file(text: &str) -> Result<Record> {
+    let mut meta = RecordMeta::default();
+    let mut soup: Option<Soup> = None;
+    let (mut x0, mut y0) = (0, 0);
+    let mut description: Vec<&str> = vec![];
+    for line in text.lines() {
+        let line = line.trim_end();
+        if let Some(rest) = line.strip_prefix("#CXRLE") {
+            for field in rest.split_whitespace() {
+                if let Some(pos) = field.strip_prefix("Pos=") {
+                    let (x, y) = pos.split_once(',').unwrap_or(("0", "0"));
+                    x0 = x.parse().unwrap_or(0);
+                    y0 = y.parse().unwrap_or(0);
+                } else if let Some(gen) = field.strip_prefix("Gen=") {
+                    meta.generation = gen.parse().unwrap_or(0);
+                }
+            }
+        } else if let Some(rest) = line.strip_prefix("#CLC ") {
+            let (key, value) = rest.split_once(' ').unwrap_or((rest, ""));
+            match key {
+                "tags" => {
+                    meta.tags = value
+                        .split(',')
+                        .filter(|t| !t.is_empty())
+                        .map(String::from)
+                        .collect()
+                }
+                "created" => meta.created = value.parse().unwrap_or(0),
+                "modified" => meta.modified = value.parse().unwrap_or(0),
+                "soup" => {
+                    let v: Vec<&str> = value.split_whitespace().collect();
+                    if v.len() == 7 {
+                        soup = Some(Soup {
+                            x: v[0].parse().unwrap_or(0),
+                            y: v[1].parse().unwrap_or(0),
+                            width: v[2].parse().unwrap_or(0),
+                            height: v[3].parse().unwrap_or(0),
+                            density: v[4].parse().unwrap_or(0.0),
+                            symmetry: Symmetry::parse(v[5]).unwrap_or(Symmetry::C1),
+                            seed: v[6].parse().unwrap_or(0),
+                        })
+                        .filter(|sp| sp.check().is_ok());
+                    }
+                }
+                _ => {}
+            }
+        } else if let Some(rest) = line.strip_prefix("#O") {
+            meta.author = rest.trim().to_string();
+        } else if let Some(rest) = line.strip_prefix("#C").or_else(|| line.strip_prefix("#c")) {
+            description.push(rest.strip_prefix(' ').unwrap_or(rest));
+        } else if line.starts_with('x') {
+            if let Some((_, rule)) = line.split_once("rule") {
+                meta.rule = rule.trim_start_matches([' ', '=']).trim().to_string();
+            }
+        }
+    }
+    meta.description = description.join("\n");
+    let cells: Vec<(i32, i32)> = rle::parse(text)
+        .map_err(Error::Corrupted)?
+        .into_iter()
+        .map(|(x, y)| (x + x0, y + y0))
+        .collect();
+    let set: HashSet<(i32, i32)> = cells.iter().cloned().collect();
+    meta.population = set.len() as i64;
+    meta.bbox = universe::bbox(&set);
+    Ok((cells, soup, meta))
+}
+
+impl DirStore {
+    /// Uses the directory, creating it if needed
+    pub fn new(dir: &Path) -> Result<DirStore> {
+        fs::create_dir_all(dir)?;
+        Ok(DirStore {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(file_name(name))
+    }
+
+    fn read(&self, name: &str) -> Result<Record> {
+        let text = match fs::read_to_string(self.path(name)) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(name.to_string()))
+            }
+            res => res?,
+        };
+        read_rle_file(&text)
+    }
+
+    /// Contents of the record files
+    fn files(&self) -> Result<HashMap<PathBuf, String>> {
+        let mut res: HashMap<PathBuf, String> = HashMap::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == RLE_EXT) {
+                let text = fs::read_to_string(&path)?;
+                res.insert(path, text);
+            }
+        }
+        Ok(res)
+    }
+
+    fn write(
+        &self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+    ) -> Result<()> {
+        // Written aside and renamed, so that a failure does not leave half a file
+        let tmp = self.dir.join(format!(".{}.tmp", file_name(name)));
+        fs::write(&tmp, write_rle_file(name, cells, soup, meta))?;
+        fs::rename(&tmp, self.path(name))?;
+        Ok(())
+    }
+}
+
+impl PatternStore for DirStore {
+    fn list(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == RLE_EXT) {
+                if let Some(name) = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .and_then(record_name)
+                {
+                    names.push(name);
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn save(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+    ) -> Result<()> {
+        if self.path(name).exists() {
+            return Err(Error::DuplicateName(name.to_string()));
+        }
+        let ts = db::now();
+        self.write(name, cells, soup, &_stamp_meta(cells, meta, ts, ts))
+    }
+
+    fn overwrite(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+    ) -> Result<()> {
+        let created = self.read(name)?.2.created;
+        self.write(
+            name,
+            cells,
+            soup,
+            &_stamp_meta(cells, meta, created, db::now()),
+        )
+    }
+
+    fn import_record(
+        &mut self,
+        name: &str,
+        cells: &HashSet<(i32, i32)>,
+        soup: Option<&Soup>,
+        meta: &RecordMeta,
+        replace: bool,
+    ) -> Result<()> {
+        match (replace, self.path(name).exists()) {
+            (true, false) => return Err(Error::NotFound(name.to_string())),
+            (false, true) => return Err(Error::DuplicateName(name.to_string())),
+            _ => {}
+        }
+        let (created, modified) = kept_times(meta);
+        self.write(
+            name,
+            cells,
+            soup,
+            &_stamp_meta(cells, meta, created, modified),
+        )
+    }
+
+    /// The files are read beforehand and written back when `f` fails
+    fn atomically(&mut self, f: &mut dyn FnMut(&mut dyn PatternStore) -> Result<()>) -> Result<()> {
+        let before = self.files()?;
+        let res = f(self);
+        if res.is_err() {
+            let mut after = self.files()?;
+            for (path, text) in before {
+                if after.remove(&path).as_ref() != Some(&text) {
+                    let file = path.file_name().unwrap().to_string_lossy();
+                    let tmp = self.dir.join(format!(".{}.tmp", file));
+                    fs::write(&tmp, text)?;
+                    fs::rename(&tmp, &path)?;
+                }
+            }
+            // Files added by `f`
+            for path in after.into_keys() {
+                fs::remove_file(path)?;
+            }
+        }
+        res
+    }
+
+    fn load(&self, name: &str) -> Result<Vec<(i32, i32)>> {
+        Ok(self.read(name)?.0)
+    }
+
+    fn load_meta(&self, name: &str) -> Result<RecordMeta> {
+        Ok(self.read(name)?.2)
+    }
+
+    fn load_soup(&self, name: &str) -> Result<Option<Soup>> {
+        Ok(self.read(name)?.1)
+    }
+
+    fn delete(&mut self, name: &str) -> Result<()> {
+        match fs::remove_file(self.path(name)) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::NotFound(name.to_string()))
+            }
+            res => Ok(res?),
+        }
+    }
+
+    fn rename(&mut self, name: &str, new_name: &str) -> Result<()> {
+        let (cells, soup, mut meta) = self.read(name)?;
+        if self.path(new_name).exists() {
+            return Err(Error::DuplicateName(new_name.to_string()));
+        }
+        meta.modified = db::now();
+        let cells: HashSet<(i32, i32)> = cells.into_iter().collect();
+        // The name is also in the file
+        self.write(new_name, &cells, soup.as_ref(), &meta)?;
+        self.delete(name)
+    }
+}
+
+/// Opens the archive: a directory is a DirStore, ":memory:" a scratch
+/// MemoryStore, anything else an SQLite database.
+pub fn open(path: &Path) -> Result<Box<dyn PatternStore>> {
+    if path.is_dir() {
+        Ok(Box::new(DirStore::new(path)?))
+    } else if path == Path::new(":memory:") {
+        Ok(Box::new(MemoryStore::new()))
+    } else {
+        Ok(Box::new(Storage::new(&path.to_string_lossy())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_store(store: &mut dyn PatternStore) {
+        let glider: HashSet<(i32, i32)> = patterns::get("Glider")
+            .unwrap()
+            .into_iter()
+            .map(|(x, y)| (x - 5, y + 7))
+            .collect();
+        let soup = Soup {
+            x: -5,
+            y: 7,
+            width: 3,
+            height: 3,
+            density: 0.5,
+            symmetry: Symmetry::D2,
+            seed: 42,
+        };
+        let meta = RecordMeta {
+            description: String::from("A glider\nmoving"),
+            author: String::from("me"),
+            rule: String::from("B3/S23"),
+            generation: 4,
+            tags: vec![String::from("ship"), String::from("c/4")],
+            ..Default::default()
+        };
+        store.save("glider/1", &glider, Some(&soup), &meta).unwrap();
+        store
+            .save("empty", &HashSet::new(), None, &RecordMeta::default())
+            .unwrap();
+        assert!(matches!(
+            store.save("empty", &HashSet::new(), None, &meta),
+            Err(Error::DuplicateName(_))
+        ));
+        assert_eq!(store.list().unwrap(), vec!["empty", "glider/1"]);
+        let mut loaded = store.load("glider/1").unwrap();
+        loaded.sort();
+        let mut expected: Vec<(i32, i32)> = glider.iter().cloned().collect();
+        expected.sort();
+        assert_eq!(loaded, expected);
+        let m = store.load_meta("glider/1").unwrap();
+        assert_eq!(
+            (m.description.as_str(), m.author.as_str(), m.rule.as_str()),
+            ("A glider\nmoving", "me", "B3/S23")
+        );
+        assert_eq!((m.generation, m.population, m.bbox), (4, 5, (-5, 7, 3, 3)));
+        assert_eq!(m.tags, meta.tags);
+        assert_eq!(store.load_soup("glider/1").unwrap(), Some(soup));
+        assert_eq!(store.load_soup("empty").unwrap(), None);
+        store.rename("glider/1", "glider").unwrap();
+        store.duplicate("glider", "copy").unwrap();
+        assert!(matches!(
+            store.rename("copy", "empty"),
+            Err(Error::DuplicateName(_))
+        ));
+        assert_eq!(
+            store.duplicates(false).unwrap(),
+            vec![vec!["copy", "glider"]]
+        );
+        store.delete("copy").unwrap();
+        assert!(matches!(store.load("copy"), Err(Error::NotFound(_))));
+        assert!(matches!(store.delete("copy"), Err(Error::NotFound(_))));
+        store
+            .overwrite("glider", &HashSet::new(), None, &meta)
+            .unwrap();
+        assert_eq!(store.load_meta("glider").unwrap().population, 0);
+        assert_eq!(store.list().unwrap(), vec!["empty", "glider"]);
+        // A failure undoes the changes made so far
+        let res = store.atomically(&mut |s| {
+            s.delete("empty")?;
+            s.save("new", &glider, None, &meta)?;
+            s.overwrite("glider", &glider, None, &meta)?;
+            Err(Error::NotFound(String::from("anything")))
+        });
+        assert!(res.is_err());
+        assert_eq!(store.list().unwrap(), vec!["empty", "glider"]);
+        assert_eq!(store.load_meta("glider").unwrap().population, 0);
+        if !store.has_revisions() {
+            assert!(matches!(
+                store.restore_revision("glider", 1),
+                Err(Error::Unsupported(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_stores() {
+        check_store(&mut MemoryStore::new());
+        check_store(&mut Storage::new(":memory:").unwrap());
+        let dir = std::env::temp_dir().join(format!("lf-dirstore-{}", std::process::id()));
+        check_store(&mut DirStore::new(&dir).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_names() {
+        for name in ["plain name", "a/b", ".hidden", "100%", "ünïcode ✓"] {
+            let file = file_name(name);
+            assert!(!file.contains('/') && !file.starts_with('.'));
+            let stem = file.strip_suffix(".rle").unwrap();
+            assert_eq!(record_name(stem).as_deref(), Some(name));
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+
+/// Symmetry groups used for soups, named the way apgsearch names them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    C1,
+    C2,
+    C4,
+    D2,
+    D4,
+    D8,
+}
+
+pub const ALL: [Symmetry; 6] = [
+    Symmetry::C1,
+    Symmetry::C2,
+    Symmetry::C4,
+    Symmetry::D2,
+    Symmetry::D4,
+    Symmetry::D8,
+];
+
+// Transformations work on doubled coordinates relative to the centre, so
+// that centres between two cells are representable.
+type Transform = fn(i32, i32) -> (i32, i32);
+
+const IDENTITY: Transform = |u, v| (u, v);
+const ROT90: Transform = |u, v| (-v, u);
+const ROT180: Transform = |u, v| (-u, -v);
+const ROT270: Transform = |u, v| (v, -u);
+const MIRROR_X: Transform = |u, v| (-u, v);
+const MIRROR_Y: Transform = |u, v| (u, -v);
+const DIAG: Transform = |u, v| (v, u);
+const ANTIDIAG: Transform = |u, v| (-v, -u);
+
+impl Symmetry {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Symmetry::C1 => "C1",
+            Symmetry::C2 => "C2",
+            Symmetry::C4 => "C4",
+            Symmetry::D2 => "D2",
+            Symmetry::D4 => "D4",
+            Symmetry::D8 => "D8",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Symmetry> {
+        ALL.iter()
+            .find(|sym| sym.name().eq_ignore_ascii_case(s.trim()))
+            .copied()
+    }
+
+    fn transforms(&self) -> &'static [Transform] {
+        match self {
+            Symmetry::C1 => &[IDENTITY],
+            Symmetry::C2 => &[IDENTITY, ROT180],
+            Symmetry::C4 => &[IDENTITY, ROT90, ROT180, ROT270],
+            Symmetry::D2 => &[IDENTITY, MIRROR_X],
+            Symmetry::D4 => &[IDENTITY, MIRROR_X, MIRROR_Y, ROT180],
+            Symmetry::D8 => &[
+                IDENTITY, ROT90, ROT180, ROT270, MIRROR_X, MIRROR_Y, DIAG, ANTIDIAG,
+            ],
+        }
+    }
+
+    /// Images of the cell under the group, `centre2` being the doubled
+    /// coordinates of the centre. Images that fall between cells are skipped.
+    pub fn images(&self, centre2: (i32, i32), cell: (i32, i32)) -> Vec<(i32, i32)> {
+        _orbit(self.transforms(), centre2, cell)
+    }
+}
+
+fn _orbit(transforms: &[Transform], centre2: (i32, i32), cell: (i32, i32)) -> Vec<(i32, i32)> {
+    let (cx2, cy2) = centre2;
+    let (u, v) = (2 * cell.0 - cx2, 2 * cell.1 - cy2);
+    let mut res: Vec<(i32, i32)> = vec![];
+    for t in transforms {
+        let (tu, tv) = t(u, v);
+        let (x2, y2) = (tu + cx2, tv + cy2);
+        if x2 % 2 == 0 && y2 % 2 == 0 && !res.contains(&(x2 / 2, y2 / 2)) {
+            res.push((x2 / 2, y2 / 2));
+        }
+    }
+    res
+}
+
+/// Symmetry applied to the edits in edit mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawSymmetry {
+    Off,
+    MirrorX,
+    MirrorY,
+    Rot2,
+    Rot4,
+    Dihedral8,
+}
+
+impl DrawSymmetry {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DrawSymmetry::Off => "off",
+            DrawSymmetry::MirrorX => "mirror X",
+            DrawSymmetry::MirrorY => "mirror Y",
+            DrawSymmetry::Rot2 => "2-fold",
+            DrawSymmetry::Rot4 => "4-fold",
+            DrawSymmetry::Dihedral8 => "8-fold",
+        }
+    }
+
+    pub fn next(&self) -> DrawSymmetry {
+        match self {
+            DrawSymmetry::Off => DrawSymmetry::MirrorX,
+            DrawSymmetry::MirrorX => DrawSymmetry::MirrorY,
+            DrawSymmetry::MirrorY => DrawSymmetry::Rot2,
+            DrawSymmetry::Rot2 => DrawSymmetry::Rot4,
+            DrawSymmetry::Rot4 => DrawSymmetry::Dihedral8,
+            DrawSymmetry::Dihedral8 => DrawSymmetry::Off,
+        }
+    }
+
+    fn transforms(&self) -> &'static [Transform] {
+        match self {
+            DrawSymmetry::Off => &[IDENTITY],
+            DrawSymmetry::MirrorX => &[IDENTITY, MIRROR_X],
+            DrawSymmetry::MirrorY => &[IDENTITY, MIRROR_Y],
+            DrawSymmetry::Rot2 => Symmetry::C2.transforms(),
+            DrawSymmetry::Rot4 => Symmetry::C4.transforms(),
+            DrawSymmetry::Dihedral8 => Symmetry::D8.transforms(),
+        }
+    }
+
+    pub fn images(&self, centre2: (i32, i32), cell: (i32, i32)) -> Vec<(i32, i32)> {
+        _orbit(self.transforms(), centre2, cell)
+    }
+
+    /// Whether the cell touches one of the mirror axes (or is the centre of a
+    /// rotation), for drawing the axes.
+    pub fn on_axis(&self, centre2: (i32, i32), cell: (i32, i32)) -> bool {
+        let (u, v) = (2 * cell.0 - centre2.0, 2 * cell.1 - centre2.1);
+        let vertical = u.abs() <= 1;
+        let horizontal = v.abs() <= 1;
+        match self {
+            DrawSymmetry::Off => false,
+            DrawSymmetry::MirrorX => vertical,
+            DrawSymmetry::MirrorY => horizontal,
+            DrawSymmetry::Rot2 | DrawSymmetry::Rot4 => vertical && horizontal,
+            DrawSymmetry::Dihedral8 => {
+                vertical || horizontal || (u - v).abs() <= 1 || (u + v).abs() <= 1
+            }
+        }
+    }
+}
+
+impl fmt::Display for Symmetry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_symmetry_images() {
+        // Centre on the corner between (0, 0) and (1, 1)
+        let mut imgs = DrawSymmetry::Rot4.images((1, 1), (0, 0));
+        imgs.sort();
+        assert_eq!(imgs, vec![(0, 0), (0, 1), (1, 0), (1, 1)]);
+        // The centre cell is its own image
+        assert_eq!(DrawSymmetry::Dihedral8.images((4, 4), (2, 2)), vec![(2, 2)]);
+        assert_eq!(
+            DrawSymmetry::MirrorY.images((0, 2), (5, -1)),
+            vec![(5, -1), (5, 3)]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::render::Colour;
+
+/// Generations over which the changes of the cells are counted
+pub const WINDOW: usize = 32;
+
+/// Generations a dead cell stays visible as a trail
+pub const TRAIL: u32 = 8;
+
+/// How the field view colours the cells
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewMode {
+    Plain,
+    /// Live cells by the number of generations they have been alive
+    Age,
+    /// All cells by how often they changed over the last WINDOW generations
+    Heat,
+    /// Recently dead cells, fading out
+    Trails,
+}
+
+impl ViewMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewMode::Plain => "plain",
+            ViewMode::Age => "age",
+            ViewMode::Heat => "heat",
+            ViewMode::Trails => "trails",
+        }
+    }
+
+    pub fn next(&self) -> ViewMode {
+        match self {
+            ViewMode::Plain => ViewMode::Age,
+            ViewMode::Age => ViewMode::Heat,
+            ViewMode::Heat => ViewMode::Trails,
+            ViewMode::Trails => ViewMode::Plain,
+        }
+    }
+}
+
+/// What the view modes show, gathered step by step. Cells alive when the
+/// tracking starts count as newborn.
+#[derive(Default)]
+pub struct Tracker {
+    ages: HashMap<(i32, i32), u32>,
+    // Cells changed by each of the last steps, and the count per cell
+    changes: VecDeque<Vec<(i32, i32)>>,
+    heat: HashMap<(i32, i32), u32>,
+    // Generations since the death of the cell
+    trails: HashMap<(i32, i32), u32>,
+}
+
+impl Tracker {
+    pub fn clear(&mut self) {
+        *self = Tracker::default();
+    }
+
+    /// Takes one step into account, from `prev` to `next`
+    pub fn observe(&mut self, prev: &HashSet<(i32, i32)>, next: &HashSet<(i32, i32)>) {
+        self.ages = next
+            .iter()
+            .map(|c| {
+                let age = if prev.contains(c) {
+                    self.ages.get(c).map_or(1, |a| a + 1)
+                } else {
+                    0
+                };
+                (*c, age)
+            })
+            .collect();
+
+        let changed: Vec<(i32, i32)> = prev.symmetric_difference(next).cloned().collect();
+        for c in changed.iter() {
+            *self.heat.entry(*c).or_insert(0) += 1;
+        }
+        self.changes.push_back(changed);
+        if self.changes.len() > WINDOW {
+            for c in self.changes.pop_front().unwrap() {
+                if let Some(n) = self.heat.get_mut(&c) {
+                    *n -= 1;
+                    if *n == 0 {
+                        self.heat.remove(&c);
+                    }
+                }
+            }
+        }
+
+        self.trails.retain(|c, d| {
+            *d += 1;
+            *d <= TRAIL && !next.contains(c)
+        });
+        for c in prev.difference(next) {
+            self.trails.insert(*c, 1);
+        }
+    }
+
+    /// Generations the live cell has been alive, 0 if unknown
+    pub fn age(&self, cell: (i32, i32)) -> u32 {
+        self.ages.get(&cell).copied().unwrap_or(0)
+    }
+
+    /// Share of the steps of the window that changed the cell, 0 to 1
+    pub fn heat(&self, cell: (i32, i32)) -> f64 {
+        match self.heat.get(&cell) {
+            Some(n) => *n as f64 / self.changes.len() as f64,
+            None => 0.0,
+        }
+    }
+
+    /// Generations since the cell died, if it did recently
+    pub fn trail(&self, cell: (i32, i32)) -> Option<u32> {
+        self.trails.get(&cell).copied()
+    }
+}
+
+/// Colour at `t`, from 0 to 1, of a gradient through the stops
+pub fn gradient(stops: &[Colour], t: f64) -> Colour {
+    let t = t.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+    let i = (t.floor() as usize).min(stops.len().saturating_sub(2));
+    let (a, b) = (stops[i], stops[(i + 1).min(stops.len() - 1)]);
+    let f = t - i as f64;
+    let mut c = [0u8; 3];
+    for (k, v) in c.iter_mut().enumerate() {
+        *v = (a[k] as f64 + (b[k] as f64 - a[k] as f64) * f).round() as u8;
+    }
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker() {
+        let blinker: HashSet<(i32, i32)> = [(0, 0), (1, 0), (2, 0)].into_iter().collect();
+        let mut t = Tracker::default();
+        let mut field = blinker.clone();
+        for _ in 0..4 {
+            let prev = field.clone();
+            crate::rule::LIFE.step(&mut field);
+            t.observe(&prev, &field);
+        }
+        assert_eq!(field, blinker);
+        assert_eq!((t.age((1, 0)), t.age((0, 0))), (4, 0));
+        assert_eq!((t.heat((1, 0)), t.heat((0, 0))), (0.0, 1.0));
+        assert_eq!((t.trail((1, 1)), t.trail((1, 0))), (Some(1), None));
+        assert_eq!(gradient(&[[0, 0, 0], [200, 100, 0]], 0.5), [100, 50, 0]);
+        assert_eq!(
+            gradient(&[[0, 0, 0], [200, 100, 0], [0, 0, 0]], 1.0),
+            [0, 0, 0]
+        );
+    }
+}
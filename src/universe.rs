@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+
+use crate::rule::{self, Rule};
+
+/// Live cells of an unbounded plane, with the rule that evolves them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Universe {
+    pub cells: HashSet<(i32, i32)>,
+    pub rule: Rule,
+    pub generation: i64,
+}
+
+impl Default for Universe {
+    fn default() -> Universe {
+        Universe::new(rule::LIFE)
+    }
+}
+
+impl Universe {
+    pub fn new(rule: Rule) -> Universe {
+        Universe {
+            cells: HashSet::new(),
+            rule,
+            generation: 0,
+        }
+    }
+
+    pub fn with_cells<I: IntoIterator<Item = (i32, i32)>>(rule: Rule, cells: I) -> Universe {
+        Universe {
+            cells: cells.into_iter().collect(),
+            ..Universe::new(rule)
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.step_counted();
+    }
+
+    /// Steps, returns the numbers of cells born and dead
+    pub fn step_counted(&mut self) -> (usize, usize) {
+        self.generation += 1;
+        self.rule.step_counted(&mut self.cells)
+    }
+
+    /// Advances by `generations` steps
+    pub fn run(&mut self, generations: i64) {
+        for _ in 0..generations {
+            self.step();
+        }
+    }
+
+    pub fn population(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Bounding box as (x, y, width, height)
+    pub fn bbox(&self) -> (i32, i32, i32, i32) {
+        bbox(&self.cells)
+    }
+}
+
+/// Bounding box as (x, y, width, height); sizes beyond i32::MAX are
+/// given as i32::MAX
+pub fn bbox(cells: &HashSet<(i32, i32)>) -> (i32, i32, i32, i32) {
+    if cells.is_empty() {
+        return (0, 0, 0, 0);
+    }
+    let x0 = cells.iter().map(|c| c.0).min().unwrap();
+    let x1 = cells.iter().map(|c| c.0).max().unwrap();
+    let y0 = cells.iter().map(|c| c.1).min().unwrap();
+    let y1 = cells.iter().map(|c| c.1).max().unwrap();
+    let size = |a: i32, b: i32| (b as i64 - a as i64 + 1).min(i32::MAX as i64) as i32;
+    (x0, y0, size(x0, x1), size(y0, y1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patterns;
+
+    #[test]
+    fn test_glider() {
+        let glider = patterns::get("Glider").unwrap();
+        let mut u = Universe::with_cells(rule::LIFE, glider.iter().cloned());
+        let (x, y, w, h) = u.bbox();
+        u.run(4);
+        assert_eq!((u.generation, u.population()), (4, 5));
+        let (x4, y4, w4, h4) = u.bbox();
+        assert_eq!(((x4 - x).abs(), (y4 - y).abs(), w4, h4), (1, 1, w, h));
+    }
+}